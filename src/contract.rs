@@ -1,11 +1,13 @@
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
+use std::cmp::Ordering;
+
 use cosmwasm_std::{
-    coin, from_binary, to_binary, Addr, Binary, Decimal, Deps, DepsMut, Env, MessageInfo, Response, StdError, StdResult, Uint128 
+    coin, from_binary, to_binary, Addr, Api, BankMsg, Binary, BlockInfo, CosmosMsg, DistributionMsg, StakingMsg, Decimal, Deps, DepsMut, Env, MessageInfo, Order, Response, StdError, StdResult, Storage, Timestamp, Uint128, WasmMsg 
 };
 
-use cw2::set_contract_version;
-use cw20::{Cw20QueryMsg, BalanceResponse, Cw20ReceiveMsg};
+use cw2::{get_contract_version, set_contract_version};
+use cw20::{Cw20ExecuteMsg, Cw20QueryMsg, BalanceResponse, Cw20ReceiveMsg};
 use cw20_base::allowances::{
     execute_burn_from, execute_decrease_allowance, execute_increase_allowance, execute_send_from,
     execute_transfer_from, query_allowance,
@@ -13,15 +15,42 @@ use cw20_base::allowances::{
 use cw20_base::contract::{
     execute_burn, execute_mint, execute_send, execute_transfer, query_balance, query_token_info,
 };
-use cw20_base::state::{MinterData, TokenInfo, TOKEN_INFO};
-use cw_utils::Duration;
+use cw20_base::state::{MinterData, TokenInfo, BALANCES, TOKEN_INFO};
+use cw_storage_plus::Bound;
+use cw_utils::{must_pay, Duration, Expiration};
+use semver::Version;
 
 use crate::error::ContractError;
-use crate::msg::{Cw20HookMsg, ExecuteMsg, InstantiateMsg, InvestmentResponse, LockType, QueryMsg};
-use crate::state::{ LockPrd, LockTax, StakingInfo, Supply,   STAKING_INFO, TOTAL_SUPPLY};
+use crate::migrations::run_migrations;
+use crate::msg::{
+    AllNftInfoResponse, Approval, Cw20HookMsg, Cw721ReceiveMsg, ExecuteMsg, FeesResponse,
+    InstantiateMsg, InvestmentResponse, MigrateMsg, NftContractInfoResponse,
+    NftInfoResponse, NumTokensResponse, OwnerOfResponse, OwnershipResponse,
+    PendingRewardsResponse, PositionReceipt, PositionResponse, PositionsResponse, QueryMsg,
+    StakingMode, SurplusPolicy, SweepToken, TierConfig, TierResponse, TiersResponse, TokensResponse,
+    TransferableBalanceResponse, UnlockStrategy,
+};
+use crate::state::{
+    positions, Delegation, Boost, Fees, LockPosition, LockTier, PendingOwner,
+    PositionApproval, RewardPool, RewardSchedule, StakingInfo, Supply, BOOST, CLAIMS, FEES,
+    OPERATORS, PENDING_OWNER, POSITION_COUNT, REWARD_POOL, REWARD_SCHEDULE, STAKING_INFO, TIERS,
+    TOTAL_SUPPLY, USER_REWARDS,
+};
 
 
 const FALLBACK_RATIO: Decimal = Decimal::one();
+// the share math counts one more share backed by one more token than the pool has. A donation
+// inflating the exchange rate mostly goes to that virtual share, so it costs more than it takes
+const VIRTUAL_OFFSET: Uint128 = Uint128::new(1);
+
+const SECONDS_PER_YEAR: u64 = 365 * 24 * 60 * 60;
+
+// same limit as cw20-base
+const MAX_DECIMALS: u8 = 18;
+
+// settings for pagination
+const MAX_LIMIT: u32 = 30;
+const DEFAULT_LIMIT: u32 = 10;
 
 // version info for migration info
 const CONTRACT_NAME: &str = "crates.io:cw20-staking";
@@ -36,6 +65,12 @@ pub fn instantiate(
 ) -> Result<Response, ContractError> {
     set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
 
+    if msg.decimals > MAX_DECIMALS {
+        return Err(ContractError::InvalidDecimals {
+            decimals: msg.decimals,
+        });
+    }
+
     // store token info using cw20-base format
     let data = TokenInfo {
         name: msg.name,
@@ -54,48 +89,120 @@ pub fn instantiate(
     let staking_info = StakingInfo {
         owner: info.sender,
         stake_denom: msg.stake_denom,
-        staking_token_address: deps.api.addr_canonicalize(&msg.staking_token_address)?,
-        period: LockPrd {
-            long: Duration::Time(msg.long_period),
-            short: Duration::Time(msg.short_period)
-        },
-        tax: LockTax {
-            long: Decimal::percent(msg.long_tax),
-            short: Decimal::percent(msg.short_tax)
-        },
-        penalty: Decimal::percent(msg.penalty)
+        staking_token_address: deps
+            .api
+            .addr_canonicalize(deps.api.addr_validate(&msg.staking_token_address)?.as_str())?,
+        reward_token_address: msg
+            .reward_token_address
+            .map(|addr| deps.api.addr_canonicalize(deps.api.addr_validate(&addr)?.as_str()))
+            .transpose()?,
+        surplus_policy: msg.surplus_policy.unwrap_or_default(),
+        lock_transfers: msg.lock_transfers.unwrap_or_default(),
+        staking_mode: msg.staking_mode.unwrap_or_default(),
+        delegation: msg.delegation.map(|delegation| Delegation {
+            validators: delegation.validators,
+            unbonding_period: Duration::Time(delegation.unbonding_period),
+        }),
     };
 
+    if let Some(delegation) = &staking_info.delegation {
+        validate_delegation(deps.as_ref(), &staking_info, delegation)?;
+    }
     STAKING_INFO.save(deps.storage, &staking_info)?;
+    for tier in msg.tiers {
+        if TIERS.has(deps.storage, &tier.id) {
+            return Err(ContractError::DuplicateTier { id: tier.id });
+        }
+        save_tier(deps.storage, tier)?;
+    }
 
     // set supply to 0
     let supply = Supply::default();
     TOTAL_SUPPLY.save(deps.storage, &supply)?;
+    FEES.save(deps.storage, &Fees::default())?;
+    BOOST.save(deps.storage, &Boost::default())?;
+    REWARD_POOL.save(deps.storage, &RewardPool::default())?;
 
     Ok(Response::default())
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn execute(
-    deps: DepsMut,
+    mut deps: DepsMut,
     env: Env,
     info: MessageInfo,
     msg: ExecuteMsg,
 ) -> Result<Response, ContractError> {
+    let api = deps.api;
     match msg {
-        ExecuteMsg::Receive(msg) => receive_cw20(deps, env, info, msg),       
-        ExecuteMsg::UnLock { amount } => unlock(deps, env, info, amount),
+        ExecuteMsg::Receive(msg) => receive_cw20(deps, env, info, msg),
+        ExecuteMsg::Lock { tier } => lock_native(deps, env, info, tier),
+        ExecuteMsg::UnLock {
+            amount,
+            token_id,
+            position_ids,
+            strategy,
+        } => {
+            let selection = PositionSelection::new(token_id, position_ids, strategy)?;
+            unlock(deps, env, info, amount, selection)
+        }
+        ExecuteMsg::ExtendLock { position_id, tier } => {
+            extend_lock(deps, env, info, position_id, tier)
+        }
+        ExecuteMsg::Claim {} => claim(deps, env, info),
+        ExecuteMsg::ClaimRewards {} => claim_rewards(deps, info),
+        ExecuteMsg::Reinvest {} => reinvest(deps, env),
+        ExecuteMsg::_BondAllTokens {} => bond_all_tokens(deps, env, info),
+        ExecuteMsg::WithdrawFees { recipient, amount } => {
+            withdraw_fees(deps, env, info, recipient, amount)
+        }
+        ExecuteMsg::Sweep { token, recipient } => sweep(deps, env, info, token, recipient),
+        ExecuteMsg::SetTier { tier } => set_tier(deps, info, tier),
+        ExecuteMsg::RemoveTier { id } => remove_tier(deps, info, id),
+        ExecuteMsg::UpdateConfig {
+            reward_token_address,
+            surplus_policy,
+            lock_transfers,
+        } => update_config(
+            deps,
+            info,
+            reward_token_address,
+            surplus_policy,
+            lock_transfers,
+        ),
+        ExecuteMsg::ProposeNewOwner { owner, expiry } => {
+            propose_new_owner(deps, env, info, owner, expiry)
+        }
+        ExecuteMsg::AcceptOwnership {} => accept_ownership(deps, env, info),
+        ExecuteMsg::CancelOwnershipProposal {} => cancel_ownership_proposal(deps, info),
 
-        // these all come from cw20-base to implement the cw20 standard
+        // these all come from cw20-base to implement the cw20 standard,
+        // the ones moving balances settle the holders' rewards around the call
         ExecuteMsg::Transfer { recipient, amount } => {
-            Ok(execute_transfer(deps, env, info, recipient, amount)?)
+            assert_transferable(deps.as_ref(), &env.block, &info.sender, amount)?;
+            let holders = [info.sender.clone(), api.addr_validate(&recipient)?];
+            move_backing(deps.storage, &env.block, &holders[0], &holders[1], amount)?;
+            with_rewards(deps, &holders, |deps| {
+                execute_transfer(deps, env, info, recipient, amount)
+            })
+        }
+        ExecuteMsg::Burn { amount } => {
+            release_burnt(deps.branch(), &env, &info.sender, amount)?;
+            let holders = [info.sender.clone()];
+            with_rewards(deps, &holders, |deps| execute_burn(deps, env, info, amount))
         }
-        ExecuteMsg::Burn { amount } => Ok(execute_burn(deps, env, info, amount)?),
         ExecuteMsg::Send {
             contract,
             amount,
             msg,
-        } => Ok(execute_send(deps, env, info, contract, amount, msg)?),
+        } => {
+            assert_transferable(deps.as_ref(), &env.block, &info.sender, amount)?;
+            let holders = [info.sender.clone(), api.addr_validate(&contract)?];
+            move_backing(deps.storage, &env.block, &holders[0], &holders[1], amount)?;
+            with_rewards(deps, &holders, |deps| {
+                execute_send(deps, env, info, contract, amount, msg)
+            })
+        }
         ExecuteMsg::IncreaseAllowance {
             spender,
             amount,
@@ -114,59 +221,640 @@ pub fn execute(
             owner,
             recipient,
             amount,
-        } => Ok(execute_transfer_from(
-            deps, env, info, owner, recipient, amount,
-        )?),
+        } => {
+            let holders = [api.addr_validate(&owner)?, api.addr_validate(&recipient)?];
+            assert_transferable(deps.as_ref(), &env.block, &holders[0], amount)?;
+            move_backing(deps.storage, &env.block, &holders[0], &holders[1], amount)?;
+            with_rewards(deps, &holders, |deps| {
+                execute_transfer_from(deps, env, info, owner, recipient, amount)
+            })
+        }
         ExecuteMsg::BurnFrom { owner, amount } => {
-            Ok(execute_burn_from(deps, env, info, owner, amount)?)
+            let holders = [api.addr_validate(&owner)?];
+            release_burnt(deps.branch(), &env, &holders[0], amount)?;
+            with_rewards(deps, &holders, |deps| {
+                execute_burn_from(deps, env, info, owner, amount)
+            })
         }
         ExecuteMsg::SendFrom {
             owner,
             contract,
             amount,
             msg,
-        } => Ok(execute_send_from(
-            deps, env, info, owner, contract, amount, msg,
-        )?),
+        } => {
+            let holders = [api.addr_validate(&owner)?, api.addr_validate(&contract)?];
+            assert_transferable(deps.as_ref(), &env.block, &holders[0], amount)?;
+            move_backing(deps.storage, &env.block, &holders[0], &holders[1], amount)?;
+            with_rewards(deps, &holders, |deps| {
+                execute_send_from(deps, env, info, owner, contract, amount, msg)
+            })
+        }
+        // cw721 interface of the position receipts
+        ExecuteMsg::TransferNft {
+            recipient,
+            token_id,
+        } => transfer_nft(deps, env, info, recipient, token_id),
+        ExecuteMsg::SendNft {
+            contract,
+            token_id,
+            msg,
+        } => send_nft(deps, env, info, contract, token_id, msg),
+        ExecuteMsg::Approve {
+            spender,
+            token_id,
+            expires,
+        } => approve(deps, env, info, spender, token_id, expires),
+        ExecuteMsg::Revoke { spender, token_id } => revoke(deps, env, info, spender, token_id),
+        ExecuteMsg::ApproveAll { operator, expires } => {
+            approve_all(deps, env, info, operator, expires)
+        }
+        ExecuteMsg::RevokeAll { operator } => revoke_all(deps, info, operator),
+    }
+}
+
+// locked_balance is the part of the owner's balance backing unmatured positions,
+// zero unless transfers are locked (unlocked transfers take the backing along, see move_backing)
+fn locked_balance(deps: Deps, block: &BlockInfo, owner: &Addr) -> StdResult<Uint128> {
+    if !STAKING_INFO.load(deps.storage)?.lock_transfers {
+        return Ok(Uint128::zero());
+    }
+    unmatured_amount(deps.storage, block, owner)
+}
+
+// unmatured_amount is how many derivative tokens of the owner's balance back unmatured positions
+fn unmatured_amount(storage: &dyn Storage, block: &BlockInfo, owner: &Addr) -> StdResult<Uint128> {
+    let unmatured: Uint128 = positions()
+        .idx
+        .owner
+        .prefix(owner.clone())
+        .range(storage, None, None, Order::Ascending)
+        .map(|item| {
+            item.map(|(_, position)| {
+                if position.release.is_expired(block) {
+                    Uint128::zero()
+                } else {
+                    position.amount
+                }
+            })
+        })
+        .sum::<StdResult<_>>()?;
+    let balance = BALANCES.may_load(storage, owner)?.unwrap_or_default();
+    Ok(unmatured.min(balance))
+}
+
+// assert_transferable stops transfers that would move tokens backing unmatured positions
+fn assert_transferable(
+    deps: Deps,
+    block: &BlockInfo,
+    owner: &Addr,
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    let locked = locked_balance(deps, block, owner)?;
+    let balance = BALANCES.may_load(deps.storage, owner)?.unwrap_or_default();
+    let transferable = balance - locked;
+    if amount > transferable {
+        return Err(ContractError::LockedBalance { transferable });
+    }
+    Ok(())
+}
+
+// release_burnt drops the positions backing burnt derivative tokens, so they stop earning the
+// boost. Tokens without a position go first, then matured positions and the ones maturing soonest.
+// The boost rewards the released parts earned become a claim of the owner. Call it before the burn
+fn release_burnt(
+    deps: DepsMut,
+    env: &Env,
+    owner: &Addr,
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    let backed: Uint128 = positions()
+        .idx
+        .owner
+        .prefix(owner.clone())
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(_, position)| position.amount))
+        .sum::<StdResult<_>>()?;
+    let balance = BALANCES.may_load(deps.storage, owner)?.unwrap_or_default();
+    let released = amount.saturating_sub(balance.saturating_sub(backed));
+    if released.is_zero() {
+        return Ok(());
+    }
+
+    accrue_rewards(deps.storage, &env.block)?;
+    let mut boost = BOOST.load(deps.storage)?;
+    let parts = release_positions(
+        deps.storage,
+        owner,
+        backed,
+        released,
+        &env.block,
+        &mut boost,
+        PositionSelection::Strategy(UnlockStrategy::MaturedFirst {}),
+    )?;
+    BOOST.save(deps.storage, &boost)?;
+    let rewards: Uint128 = parts.iter().map(|part| part.rewards).sum();
+    if !rewards.is_zero() {
+        CLAIMS.create_claim(
+            deps.storage,
+            owner,
+            rewards,
+            Expiration::AtTime(env.block.time),
+        )?;
+        TOTAL_SUPPLY.update(deps.storage, |mut supply| -> StdResult<_> {
+            supply.claims += rewards;
+            Ok(supply)
+        })?;
+    }
+    Ok(())
+}
+
+// move_backing hands the positions backing `amount` transferred tokens over to the recipient,
+// so a transfer can't turn unmatured tokens into unbacked ones. Unbacked tokens go first,
+// then matured positions and then the ones maturing soonest. Call it before the balances move.
+fn move_backing(
+    storage: &mut dyn Storage,
+    block: &BlockInfo,
+    owner: &Addr,
+    recipient: &Addr,
+    amount: Uint128,
+) -> StdResult<()> {
+    if owner == recipient {
+        return Ok(());
+    }
+    let mut owned: Vec<(u64, LockPosition)> = positions()
+        .idx
+        .owner
+        .prefix(owner.clone())
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<_>>()?;
+    let backed: Uint128 = owned.iter().map(|(_, p)| p.amount).sum();
+    let balance = BALANCES.may_load(storage, owner)?.unwrap_or_default();
+    let mut remaining = amount.saturating_sub(balance.saturating_sub(backed));
+    owned.sort_by_key(|(id, p)| match p.release.is_expired(block) {
+        true => ((0, 0), *id),
+        false => (release_key(&p.release), *id),
+    });
+
+    for (id, mut position) in owned {
+        if remaining.is_zero() {
+            break;
+        }
+        let taken = remaining.min(position.amount);
+        remaining -= taken;
+        if taken == position.amount {
+            position.owner = recipient.clone();
+            position.approvals = vec![];
+            positions().save(storage, id, &position)?;
+            continue;
+        }
+
+        // the moved part takes its share of the boost and of the boost rewards not paid yet
+        let moved = LockPosition {
+            owner: recipient.clone(),
+            amount: taken,
+            boost: position.boost.multiply_ratio(taken, position.amount),
+            boost_rewards: position.boost_rewards.multiply_ratio(taken, position.amount),
+            approvals: vec![],
+            ..position.clone()
+        };
+        position.amount -= moved.amount;
+        position.boost -= moved.boost;
+        position.boost_rewards -= moved.boost_rewards;
+        positions().save(storage, id, &position)?;
+        let moved_id = POSITION_COUNT.may_load(storage)?.unwrap_or_default() + 1;
+        POSITION_COUNT.save(storage, &moved_id)?;
+        positions().save(storage, moved_id, &moved)?;
     }
+    Ok(())
+}
+
+// settle_rewards moves what the holders earned at their current balance into their pending rewards
+fn settle_rewards(storage: &mut dyn Storage, holders: &[Addr]) -> StdResult<()> {
+    let pool = REWARD_POOL.load(storage)?;
+    for holder in holders {
+        let balance = BALANCES.may_load(storage, holder)?.unwrap_or_default();
+        let mut user = USER_REWARDS.may_load(storage, holder)?.unwrap_or_default();
+        user.pending += balance * pool.reward_per_share - user.reward_debt;
+        user.reward_debt = balance * pool.reward_per_share;
+        USER_REWARDS.save(storage, holder, &user)?;
+    }
+    Ok(())
+}
+
+// sync_reward_debts marks the rewards of the holders' new balances as accounted for,
+// call it after a balance change that was preceded by settle_rewards
+fn sync_reward_debts(storage: &mut dyn Storage, holders: &[Addr]) -> StdResult<()> {
+    let pool = REWARD_POOL.load(storage)?;
+    for holder in holders {
+        let balance = BALANCES.may_load(storage, holder)?.unwrap_or_default();
+        let mut user = USER_REWARDS.may_load(storage, holder)?.unwrap_or_default();
+        user.reward_debt = balance * pool.reward_per_share;
+        USER_REWARDS.save(storage, holder, &user)?;
+    }
+    Ok(())
+}
+
+// with_rewards runs a cw20-base balance change with the holders' rewards settled around it
+fn with_rewards<F>(mut deps: DepsMut, holders: &[Addr], f: F) -> Result<Response, ContractError>
+where
+    F: FnOnce(DepsMut) -> Result<Response, cw20_base::ContractError>,
+{
+    settle_rewards(deps.storage, holders)?;
+    let res = f(deps.branch())?;
+    sync_reward_debts(deps.storage, holders)?;
+    Ok(res)
+}
+
+fn validate_rate(name: &str, rate: Decimal) -> Result<(), ContractError> {
+    if rate > Decimal::one() {
+        return Err(ContractError::InvalidRate {
+            name: name.to_string(),
+            rate,
+        });
+    }
+    Ok(())
+}
+
+fn validate_weight(name: &str, weight: Decimal) -> Result<(), ContractError> {
+    if weight < Decimal::one() {
+        return Err(ContractError::InvalidWeight {
+            name: name.to_string(),
+            weight,
+        });
+    }
+    Ok(())
+}
+
+// validate_tier rejects tiers that would break lock and unlock
+fn validate_tier(id: &str, tier: &LockTier) -> Result<(), ContractError> {
+    if id.is_empty() {
+        return Err(ContractError::EmptyTierId {});
+    }
+    validate_rate(&format!("{}_tax", id), tier.tax)?;
+    validate_rate(&format!("{}_penalty", id), tier.penalty)?;
+    validate_weight(&format!("{}_weight", id), tier.weight)?;
+    if let Duration::Time(0) | Duration::Height(0) = tier.period {
+        return Err(ContractError::InvalidPeriod { id: id.to_string() });
+    }
+    Ok(())
+}
+
+// save_tier validates the config and stores it as the tier with its id
+fn save_tier(storage: &mut dyn Storage, config: TierConfig) -> Result<LockTier, ContractError> {
+    let tier = LockTier {
+        period: Duration::Time(config.period),
+        tax: config.tax.into(),
+        penalty: config.penalty.into(),
+        weight: config.weight.unwrap_or_else(Decimal::one),
+    };
+    validate_tier(&config.id, &tier)?;
+    TIERS.save(storage, &config.id, &tier)?;
+    Ok(tier)
+}
+
+fn load_tier(storage: &dyn Storage, id: &str) -> Result<LockTier, ContractError> {
+    TIERS
+        .may_load(storage, id)?
+        .ok_or_else(|| ContractError::TierNotFound { id: id.to_string() })
 }
 
 // get_locked returns the total amount of qtum locked on this contract
 // it ensures they are all the same denom
 fn get_locked(deps: Deps,  contract: &Addr) -> Result<Uint128, ContractError> {
 
-    let staking_token_address = STAKING_INFO.load(deps.storage)?.staking_token_address;
-    
+    let stake_info = STAKING_INFO.load(deps.storage)?;
+    // delegated coins are locked, what the contract holds itself is not
+    if stake_info.delegation.is_some() {
+        return Ok(get_bonded(deps, contract, &stake_info.stake_denom)?);
+    }
+    let balance = match stake_info.staking_mode {
+        StakingMode::Cw20 {} => {
+            let staking_token = deps.api.addr_humanize(&stake_info.staking_token_address)?;
+            query_cw20_balance(deps, &staking_token, contract)?
+        }
+        StakingMode::Native {} => {
+            deps.querier
+                .query_balance(contract, &stake_info.stake_denom)?
+                .amount
+        }
+    };
+
+    Ok(balance)
+}
+
+// validate_delegation checks the coins can be delegated to every validator
+fn validate_delegation(
+    deps: Deps,
+    stake_info: &StakingInfo,
+    delegation: &Delegation,
+) -> Result<(), ContractError> {
+    if stake_info.staking_mode != (StakingMode::Native {}) {
+        return Err(ContractError::DelegationNeedsNativeMode {});
+    }
+    if delegation.validators.is_empty() {
+        return Err(ContractError::NoValidators {});
+    }
+    for validator in &delegation.validators {
+        if deps.querier.query_validator(validator)?.is_none() {
+            return Err(ContractError::NotInValidatorSet {
+                validator: validator.clone(),
+            });
+        }
+    }
+    let bonded_denom = deps.querier.query_bonded_denom()?;
+    if bonded_denom != stake_info.stake_denom {
+        return Err(ContractError::DifferentBondDenom {
+            denom1: bonded_denom,
+            denom2: stake_info.stake_denom.clone(),
+        });
+    }
+    Ok(())
+}
+
+// get_bonded returns the total amount delegated by the contract
+fn get_bonded(deps: Deps, contract: &Addr, denom: &str) -> StdResult<Uint128> {
+    let bonds = deps.querier.query_all_delegations(contract)?;
+    Ok(bonds
+        .iter()
+        .filter(|bond| bond.amount.denom == denom)
+        .map(|bond| bond.amount.amount)
+        .sum())
+}
+
+// delegate_msgs spreads amount evenly over the validators, the first ones take the remainder
+fn delegate_msgs(delegation: &Delegation, denom: &str, amount: Uint128) -> Vec<CosmosMsg> {
+    let count = delegation.validators.len() as u128;
+    let share = amount.u128() / count;
+    let remainder = amount.u128() % count;
+    delegation
+        .validators
+        .iter()
+        .enumerate()
+        .filter_map(|(i, validator)| {
+            let amount = share + u128::from((i as u128) < remainder);
+            (amount > 0).then(|| {
+                StakingMsg::Delegate {
+                    validator: validator.clone(),
+                    amount: coin(amount, denom),
+                }
+                .into()
+            })
+        })
+        .collect()
+}
+
+// undelegate_msgs takes amount from the current delegations in proportion to their size
+fn undelegate_msgs(
+    deps: Deps,
+    contract: &Addr,
+    denom: &str,
+    amount: Uint128,
+) -> Result<Vec<CosmosMsg>, ContractError> {
+    let bonds: Vec<_> = deps
+        .querier
+        .query_all_delegations(contract)?
+        .into_iter()
+        .filter(|bond| bond.amount.denom == denom)
+        .collect();
+    let bonded: Uint128 = bonds.iter().map(|bond| bond.amount.amount).sum();
+    if bonded < amount {
+        return Err(ContractError::LockedMismatch {
+            stored: amount,
+            queried: bonded,
+        });
+    }
+
+    let mut taken: Vec<Uint128> = bonds
+        .iter()
+        .map(|bond| bond.amount.amount.multiply_ratio(amount, bonded))
+        .collect();
+    // rounding leaves a little, take it from whoever has room
+    let mut remaining = amount - taken.iter().sum::<Uint128>();
+    for (bond, taken) in bonds.iter().zip(taken.iter_mut()) {
+        let extra = remaining.min(bond.amount.amount - *taken);
+        *taken += extra;
+        remaining -= extra;
+    }
+
+    Ok(bonds
+        .into_iter()
+        .zip(taken)
+        .filter(|(_, amount)| !amount.is_zero())
+        .map(|(bond, amount)| {
+            StakingMsg::Undelegate {
+                validator: bond.validator,
+                amount: coin(amount.u128(), denom),
+            }
+            .into()
+        })
+        .collect())
+}
+
+// send_stake pays out staking tokens, as a cw20 transfer or a bank send depending on the mode
+fn send_stake(
+    api: &dyn Api,
+    stake_info: &StakingInfo,
+    recipient: &Addr,
+    amount: Uint128,
+) -> StdResult<CosmosMsg> {
+    let msg = match stake_info.staking_mode {
+        StakingMode::Cw20 {} => WasmMsg::Execute {
+            contract_addr: api
+                .addr_humanize(&stake_info.staking_token_address)?
+                .to_string(),
+            msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: recipient.to_string(),
+                amount,
+            })?,
+            funds: vec![],
+        }
+        .into(),
+        StakingMode::Native {} => BankMsg::Send {
+            to_address: recipient.to_string(),
+            amount: vec![coin(amount.u128(), &stake_info.stake_denom)],
+        }
+        .into(),
+    };
+    Ok(msg)
+}
+
+fn query_cw20_balance(deps: Deps, token: &Addr, address: &Addr) -> StdResult<Uint128> {
     let balance: BalanceResponse = deps.querier.query_wasm_smart(
-        staking_token_address.to_string(),
+        token,
         &Cw20QueryMsg::Balance {
-            address: contract.to_string(),
+            address: address.to_string(),
         },
     )?;
 
-    let locked = balance.balance;
-   
-    if locked.is_zero() {
-        return Ok(Uint128::zero());
-    };
+    Ok(balance.balance)
+}
+
+
+// accrue_schedule moves the rewards released since the last call into the pool.
+// Each share earns the same base amount into `supply.locked`, and the part earned by
+// weights above one goes to the boost accumulator.
+// While no shares are out (see has_shares) the schedule is pushed back instead of accrued
+fn accrue_schedule(
+    supply: &mut Supply,
+    boost: &mut Boost,
+    schedule: &mut RewardSchedule,
+    time: Timestamp,
+) {
+    if !has_shares(supply) {
+        // a window that passed with nobody there starts over, with the same length
+        if time >= schedule.end && schedule.total > schedule.distributed {
+            schedule.end = time.plus_seconds(schedule.end.seconds() - schedule.start.seconds());
+        }
+        if time > schedule.start && time < schedule.end {
+            schedule.total -= schedule.distributed;
+            schedule.distributed = Uint128::zero();
+            schedule.start = time;
+        }
+        return;
+    }
+    let due = schedule.emitted(time) - schedule.distributed;
+    schedule.distributed += due;
 
-    return  Ok(locked);
+    let base = due.multiply_ratio(supply.issued, supply.issued + boost.weight);
+    supply.locked += base;
+    if !boost.weight.is_zero() {
+        let extra = due - base;
+        boost.reward_per_weight += Decimal::from_ratio(extra, boost.weight);
+        boost.pending += extra;
+    }
+}
+
+// accrue_rewards applies accrue_schedule to the stored supply, call it before using the supply
+fn accrue_rewards(storage: &mut dyn Storage, block: &BlockInfo) -> StdResult<()> {
+    if let Some(mut schedule) = REWARD_SCHEDULE.may_load(storage)? {
+        let mut supply = TOTAL_SUPPLY.load(storage)?;
+        let mut boost = BOOST.load(storage)?;
+        accrue_schedule(&mut supply, &mut boost, &mut schedule, block.time);
+        TOTAL_SUPPLY.save(storage, &supply)?;
+        BOOST.save(storage, &boost)?;
+        REWARD_SCHEDULE.save(storage, &schedule)?;
+    }
+    Ok(())
+}
 
+// earned_boost is how many boost rewards the position collected and has not been paid yet
+fn earned_boost(position: &LockPosition, boost: &Boost) -> Uint128 {
+    position.boost_rewards + position.boost * (boost.reward_per_weight - position.reward_index)
 }
 
+// has_shares tells whether tokens can be added to the pool,
+// with no shares out the first locker would take it all
+fn has_shares(supply: &Supply) -> bool {
+    !supply.issued.is_zero()
+}
 
-fn assert_locks(supply: &Supply, locked: Uint128) -> Result<(), ContractError> {
-    if supply.locked != locked {
-        Err(ContractError::LockedMismatch {
-            stored: supply.locked,
-            queried: locked,
-        })
+// nominal_value is how many staking tokens one derivative token is worth
+fn nominal_value(supply: &Supply) -> Decimal {
+    if supply.issued.is_zero() {
+        FALLBACK_RATIO
     } else {
-        Ok(())
+        Decimal::from_ratio(supply.locked, supply.issued)
+    }
+}
+
+// shares_for returns how many derivative tokens `amount` staking tokens buy.
+// It rounds down, so the pool never mints more than it received
+fn shares_for(supply: &Supply, amount: Uint128) -> Uint128 {
+    amount.multiply_ratio(supply.issued + VIRTUAL_OFFSET, supply.locked + VIRTUAL_OFFSET)
+}
+
+// value_of returns how many staking tokens `shares` derivative tokens are worth.
+// It rounds down, so the pool never pays out more than the shares are worth
+fn value_of(supply: &Supply, shares: Uint128) -> Uint128 {
+    shares.multiply_ratio(supply.locked + VIRTUAL_OFFSET, supply.issued + VIRTUAL_OFFSET)
+}
+
+// shares_to_cover returns how many derivative tokens are worth at least `amount` staking tokens.
+// It rounds up, so the pool is never paid less than `amount`
+fn shares_to_cover(supply: &Supply, amount: Uint128) -> Uint128 {
+    if supply.locked.is_zero() {
+        return Uint128::zero();
+    }
+    let shares = shares_for(supply, amount);
+    if value_of(supply, shares) < amount {
+        shares + Uint128::one()
+    } else {
+        shares
+    }
+}
+
+// tracked_balance is how many staking tokens the contract should hold,
+// or have delegated when it delegates
+fn tracked_balance(storage: &dyn Storage, supply: &Supply) -> StdResult<Uint128> {
+    let stake_info = STAKING_INFO.load(storage)?;
+    if stake_info.delegation.is_some() {
+        return Ok(supply.locked);
+    }
+    // tokens waiting to be claimed, fees and rewards not yet streamed are still held by the contract
+    let undistributed = REWARD_SCHEDULE
+        .may_load(storage)?
+        .map(|schedule| schedule.undistributed())
+        .unwrap_or_default();
+    let boost = BOOST.load(storage)?;
+    let mut stored = supply.locked + supply.claims + supply.fees + undistributed + boost.pending;
+    // claimable rewards paid in the staking token are held alongside it
+    if stake_info.staking_mode == (StakingMode::Cw20 {})
+        && stake_info.reward_token_address == Some(stake_info.staking_token_address)
+    {
+        stored += REWARD_POOL.load(storage)?.pending;
+    }
+    Ok(stored)
+}
+
+/// reconcile_locks compares the queried staking token balance with what the contract tracks,
+/// `incoming` being tokens already received but not tracked yet.
+/// A deficit is an error, unless the tokens are delegated: then a validator was slashed and
+/// the loss is written off the pool. A surplus comes from tokens sent outside the hooks: it is
+/// added to the pool under the donate policy, otherwise it stays untracked for the owner to sweep.
+/// Returns the donated amount.
+fn reconcile_locks(
+    storage: &dyn Storage,
+    supply: &mut Supply,
+    stake_info: &StakingInfo,
+    locked: Uint128,
+    incoming: Uint128,
+) -> Result<Uint128, ContractError> {
+    let stored = tracked_balance(storage, supply)? + incoming;
+    if locked < stored {
+        if stake_info.delegation.is_some() {
+            supply.locked -= stored - locked;
+            return Ok(Uint128::zero());
+        }
+        return Err(ContractError::LockedMismatch {
+            stored,
+            queried: locked,
+        });
+    }
+    let surplus = locked - stored;
+    match stake_info.surplus_policy {
+        SurplusPolicy::Donate {} if has_shares(supply) => {
+            supply.locked += surplus;
+            Ok(surplus)
+        }
+        _ => Ok(Uint128::zero()),
     }
 }
 
 
+// only the staking token contract can run the staking hooks, and only in cw20 mode
+fn assert_staking_token(
+    api: &dyn Api,
+    stake_info: &StakingInfo,
+    sender: &Addr,
+) -> Result<(), ContractError> {
+    if stake_info.staking_mode != (StakingMode::Cw20 {})
+        || stake_info.staking_token_address != api.addr_canonicalize(sender.as_str())?
+    {
+        return Err(ContractError::InvalidStakingToken {});
+    }
+    Ok(())
+}
+
 pub fn receive_cw20(
     deps: DepsMut,
     env: Env,
@@ -176,246 +864,4256 @@ pub fn receive_cw20(
     let stake_info = STAKING_INFO.load(deps.storage)?;
 
     match from_binary(&cw20_msg.msg) {
-        Ok(Cw20HookMsg::Lock { lock_type}) => {
+        Ok(Cw20HookMsg::Lock { tier }) => {
             // only staking token contract can execute this message
-            if stake_info.staking_token_address != deps.api.addr_canonicalize(info.sender.as_str())? {
-                return Err(ContractError::InvalidStakingToken {});
+            assert_staking_token(deps.api, &stake_info, &info.sender)?;
+
+            let cw20_sender = deps.api.addr_validate(&cw20_msg.sender)?;
+            lock(deps, env, cw20_sender, cw20_msg.amount, tier)
+        }
+        Ok(Cw20HookMsg::DistributeRewards {}) => {
+            assert_staking_token(deps.api, &stake_info, &info.sender)?;
+
+            let cw20_sender = deps.api.addr_validate(&cw20_msg.sender)?;
+            distribute_rewards(deps, env, cw20_sender, cw20_msg.amount)
+        }
+        Ok(Cw20HookMsg::FundRewards { start, end }) => {
+            assert_staking_token(deps.api, &stake_info, &info.sender)?;
+
+            let cw20_sender = deps.api.addr_validate(&cw20_msg.sender)?;
+            if cw20_sender != stake_info.owner {
+                return Err(ContractError::Unauthorized {});
+            }
+            fund_rewards(deps, env, cw20_msg.amount, start, end)
+        }
+        Ok(Cw20HookMsg::AddRewards {}) => {
+            if stake_info.reward_token_address
+                != Some(deps.api.addr_canonicalize(info.sender.as_str())?)
+            {
+                return Err(ContractError::InvalidRewardToken {});
             }
 
             let cw20_sender = deps.api.addr_validate(&cw20_msg.sender)?;
-            lock(deps, env, cw20_sender, cw20_msg.amount, lock_type)
+            add_rewards(deps, cw20_sender, cw20_msg.amount)
         }
         Err(_) => Err(ContractError::InvalidLockType {  }),
     }
 }
 
 
-pub fn lock(deps: DepsMut, env: Env, sender: Addr, lock_amount: Uint128, lock_type: LockType) -> Result<Response, ContractError> {
-
-    let stake_info = STAKING_INFO.load(deps.storage)?;
-
-    // locked is the total number of tokens user locked to this address
-    // let locked = get_locked(deps.as_ref(), &env.contract.address)?;
-
-     // calculate to_mint and update total supply
-    let mut supply = TOTAL_SUPPLY.load(deps.storage)?;
-
-
-    let tax = match lock_type {
-        LockType::Long {} => lock_amount * stake_info.tax.long,
-        LockType::Short {} => lock_amount * stake_info.tax.short
-     };
+pub fn add_rewards(deps: DepsMut, sender: Addr, amount: Uint128) -> Result<Response, ContractError> {
+    let supply = TOTAL_SUPPLY.load(deps.storage)?;
+    if supply.issued.is_zero() {
+        return Err(ContractError::NoShares {});
+    }
 
-    let to_mint = lock_amount - tax;
+    let mut pool = REWARD_POOL.load(deps.storage)?;
+    pool.reward_per_share += Decimal::from_ratio(amount, supply.issued);
+    pool.pending += amount;
+    REWARD_POOL.save(deps.storage, &pool)?;
 
-    supply.locked = supply.locked + lock_amount;
-    supply.issued += to_mint;
+    let res = Response::new()
+        .add_attribute("action", "add_rewards")
+        .add_attribute("from", sender)
+        .add_attribute("amount", amount)
+        .add_attribute("reward_per_share", pool.reward_per_share.to_string());
+    Ok(res)
+}
 
-    TOTAL_SUPPLY.save(deps.storage, &supply)?;
+pub fn reinvest(deps: DepsMut, env: Env) -> Result<Response, ContractError> {
+    let stake_info = STAKING_INFO.load(deps.storage)?;
+    if stake_info.delegation.is_none() {
+        return Err(ContractError::NotDelegating {});
+    }
 
-      // call into cw20-base to mint the token, call as self as no one else is allowed
-      let sub_info = MessageInfo {
-        sender: env.contract.address.clone(),
+    // withdraw the rewards of every delegation, then bond them in a callback once they arrived
+    let withdraw: Vec<CosmosMsg> = deps
+        .querier
+        .query_all_delegations(&env.contract.address)?
+        .into_iter()
+        .map(|bond| {
+            DistributionMsg::WithdrawDelegatorReward {
+                validator: bond.validator,
+            }
+            .into()
+        })
+        .collect();
+    let bond_all = WasmMsg::Execute {
+        contract_addr: env.contract.address.to_string(),
+        msg: to_binary(&ExecuteMsg::_BondAllTokens {})?,
         funds: vec![],
     };
-    execute_mint(deps, env, sub_info, sender.to_string(), to_mint)?;
 
-    // bond them to the validator
     let res = Response::new()
-    .add_attribute("action", "lock")
-    .add_attribute("from", sender)
-    .add_attribute("locked", lock_amount)
-    .add_attribute("minted", to_mint);
+        .add_messages(withdraw)
+        .add_message(bond_all)
+        .add_attribute("action", "reinvest");
     Ok(res)
-
 }
 
-
-pub fn unlock(
-    mut deps: DepsMut,
+pub fn bond_all_tokens(
+    deps: DepsMut,
     env: Env,
     info: MessageInfo,
-    amount: Uint128,
 ) -> Result<Response, ContractError> {
+    // this is just meant as a call-back to ourself
+    if info.sender != env.contract.address {
+        return Err(ContractError::Unauthorized {});
+    }
     let stake_info = STAKING_INFO.load(deps.storage)?;
-   
-    // calculate tax and remainer to unlock
-    let tax = amount * stake_info.penalty;
-
-    // burn from the original caller
-    execute_burn(deps.branch(), env.clone(), info.clone(), amount)?;
-  
-
-    // re-calculate locked to ensure we have real values
-    // locked is the total number of qtum tokens users locked to this address
-    let locked = get_locked(deps.as_ref(), &env.contract.address)?;
+    let delegation = stake_info
+        .delegation
+        .ok_or(ContractError::NotDelegating {})?;
 
-    // calculate how many native tokens this is worth and update supply
-    let unlock = amount.checked_sub(tax).map_err(StdError::overflow)?;
+    // everything the contract holds beyond claims and fees is rewards, tokens still unbonding
+    // for claims are not in the balance yet so this never takes too much
     let mut supply = TOTAL_SUPPLY.load(deps.storage)?;
-    // TODO: this is just a safety assertion - do we keep it, or remove caching?
-    // in the end supply is just there to cache the (expected) results of get_bonded() so we don't
-    // have expensive queries everywhere
-    assert_locks(&supply, locked)?;
+    release_fees(deps.storage, &env, &mut supply)?;
+    let balance = deps
+        .querier
+        .query_balance(&env.contract.address, &stake_info.stake_denom)?;
+    let to_bond = balance.amount.saturating_sub(supply.claims + supply.fees);
+    if to_bond.is_zero() || !has_shares(&supply) {
+        TOTAL_SUPPLY.save(deps.storage, &supply)?;
+        return Ok(Response::default());
+    }
 
-    supply.locked = locked.checked_sub(unlock).map_err(StdError::overflow)?;
-    supply.issued = supply
-        .issued
-        .checked_sub(amount)
-        .map_err(StdError::overflow)?;
-    supply.fees += tax;
+    supply.locked += to_bond;
     TOTAL_SUPPLY.save(deps.storage, &supply)?;
 
-    // unbond them
     let res = Response::new()
-        .add_attribute("action", "unlock")
-        .add_attribute("to", info.sender)
-        .add_attribute("unlocked", unlock)
-        .add_attribute("burnt", amount);
+        .add_messages(delegate_msgs(&delegation, &stake_info.stake_denom, to_bond))
+        .add_attribute("action", "reinvest")
+        .add_attribute("bonded", to_bond)
+        .add_attribute("nominal_value", nominal_value(&supply).to_string());
     Ok(res)
 }
 
+pub fn claim_rewards(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
+    let stake_info = STAKING_INFO.load(deps.storage)?;
+    let reward_token = stake_info
+        .reward_token_address
+        .ok_or(ContractError::InvalidRewardToken {})?;
 
-#[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
-    match msg {
-       
-        QueryMsg::Investment {} => to_binary(&query_investment(deps)?),
-        // inherited from cw20-base
-        QueryMsg::TokenInfo {} => to_binary(&query_token_info(deps)?),
-        QueryMsg::Balance { address } => to_binary(&query_balance(deps, address)?),
-        QueryMsg::Allowance { owner, spender } => {
-            to_binary(&query_allowance(deps, owner, spender)?)
-        }
+    settle_rewards(deps.storage, std::slice::from_ref(&info.sender))?;
+    let mut user = USER_REWARDS.load(deps.storage, &info.sender)?;
+    let to_send = user.pending;
+    if to_send.is_zero() {
+        return Err(ContractError::NothingToClaim {});
     }
-}
+    user.pending = Uint128::zero();
+    USER_REWARDS.save(deps.storage, &info.sender, &user)?;
 
-pub fn query_investment(deps: Deps) -> StdResult<InvestmentResponse> {
-    let stake_info = STAKING_INFO.load(deps.storage)?;
-    let supply = TOTAL_SUPPLY.load(deps.storage)?;
+    let mut pool = REWARD_POOL.load(deps.storage)?;
+    pool.pending = pool
+        .pending
+        .checked_sub(to_send)
+        .map_err(StdError::overflow)?;
+    REWARD_POOL.save(deps.storage, &pool)?;
 
-    let res = InvestmentResponse {
-        owner: stake_info.owner.to_string(),
-        penalty: stake_info.penalty,
-        token_supply: supply.issued,
-        staked_tokens: coin(supply.locked.u128(), &stake_info.stake_denom),
-        nominal_value: if supply.issued.is_zero() {
-            FALLBACK_RATIO
-        } else {
-            Decimal::from_ratio(supply.locked, supply.issued)
-        },
-        period: LockPrd {
-            long: stake_info.period.long,
-            short:stake_info.period.short
-        },
-        tax: LockTax {
-            long: stake_info.tax.long,
-            short: stake_info.tax.short
-        }
+    let transfer = WasmMsg::Execute {
+        contract_addr: deps.api.addr_humanize(&reward_token)?.to_string(),
+        msg: to_binary(&Cw20ExecuteMsg::Transfer {
+            recipient: info.sender.to_string(),
+            amount: to_send,
+        })?,
+        funds: vec![],
     };
+
+    let res = Response::new()
+        .add_message(transfer)
+        .add_attribute("action", "claim_rewards")
+        .add_attribute("from", info.sender)
+        .add_attribute("amount", to_send);
     Ok(res)
 }
 
+pub fn fund_rewards(
+    deps: DepsMut,
+    env: Env,
+    amount: Uint128,
+    start: Timestamp,
+    end: Timestamp,
+) -> Result<Response, ContractError> {
+    if start >= end || end <= env.block.time {
+        return Err(ContractError::InvalidRewardSchedule {});
+    }
+    accrue_rewards(deps.storage, &env.block)?;
 
-#[cfg(test)]
+    // carry over whatever the previous schedule has not released yet
+    let leftover = REWARD_SCHEDULE
+        .may_load(deps.storage)?
+        .map(|schedule| schedule.undistributed())
+        .unwrap_or_default();
+    let schedule = RewardSchedule {
+        start,
+        end,
+        total: amount + leftover,
+        distributed: Uint128::zero(),
+    };
+    REWARD_SCHEDULE.save(deps.storage, &schedule)?;
+
+    let res = Response::new()
+        .add_attribute("action", "fund_rewards")
+        .add_attribute("amount", amount)
+        .add_attribute("total", schedule.total)
+        .add_attribute("start", start.to_string())
+        .add_attribute("end", end.to_string());
+    Ok(res)
+}
+
+// distribute_rewards adds the tokens to the pool without minting, raising the value of every share
+pub fn distribute_rewards(
+    deps: DepsMut,
+    env: Env,
+    sender: Addr,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    accrue_rewards(deps.storage, &env.block)?;
+    let mut supply = TOTAL_SUPPLY.load(deps.storage)?;
+    if !has_shares(&supply) {
+        return Err(ContractError::NoShares {});
+    }
+    supply.locked += amount;
+    TOTAL_SUPPLY.save(deps.storage, &supply)?;
+
+    let res = Response::new()
+        .add_attribute("action", "distribute_rewards")
+        .add_attribute("from", sender)
+        .add_attribute("amount", amount)
+        .add_attribute("nominal_value", nominal_value(&supply).to_string());
+    Ok(res)
+}
+
+pub fn lock_native(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    tier: String,
+) -> Result<Response, ContractError> {
+    let stake_info = STAKING_INFO.load(deps.storage)?;
+    if stake_info.staking_mode != (StakingMode::Native {}) {
+        return Err(ContractError::InvalidStakingToken {});
+    }
+
+    let amount = must_pay(&info, &stake_info.stake_denom)?;
+    lock(deps, env, info.sender, amount, tier)
+}
+
+pub fn lock(mut deps: DepsMut, env: Env, sender: Addr, lock_amount: Uint128, tier: String) -> Result<Response, ContractError> {
+
+    let stake_info = STAKING_INFO.load(deps.storage)?;
+    let lock_tier = load_tier(deps.storage, &tier)?;
+
+    // locked is the total number of tokens user locked to this address,
+    // the tokens were sent before this runs so it already holds lock_amount, unless they get delegated
+    let locked = get_locked(deps.as_ref(), &env.contract.address)?;
+    let incoming = if stake_info.delegation.is_some() {
+        Uint128::zero()
+    } else {
+        lock_amount
+    };
+
+     // calculate to_mint and update total supply
+    accrue_rewards(deps.storage, &env.block)?;
+    let mut supply = TOTAL_SUPPLY.load(deps.storage)?;
+    // stray tokens are settled before minting, so a donation goes to the existing holders
+    let donated = reconcile_locks(
+        deps.storage,
+        &mut supply,
+        &stake_info,
+        locked,
+        incoming,
+    )?;
+
+
+    let tax = lock_amount * lock_tier.tax;
+
+    // mint shares at the current exchange rate, so earlier lockers keep their gains
+    let net = lock_amount - tax;
+    let to_mint = shares_for(&supply, net);
+    if to_mint.is_zero() {
+        return Err(ContractError::InvalidZeroAmount {});
+    }
+
+    supply.locked += net;
+    supply.issued += to_mint;
+    supply.fees += tax;
+
+    TOTAL_SUPPLY.save(deps.storage, &supply)?;
+    FEES.update(deps.storage, |mut fees| -> StdResult<_> {
+        fees.tax += tax;
+        Ok(fees)
+    })?;
+
+    // record the position so unlock can tell when it matures
+    let mut boost = BOOST.load(deps.storage)?;
+    let position = LockPosition {
+        owner: sender.clone(),
+        amount: to_mint,
+        tier,
+        period: lock_tier.period,
+        tax: lock_tier.tax,
+        weight: lock_tier.weight,
+        start: env.block.time,
+        release: lock_tier.period.after(&env.block),
+        penalty: lock_tier.penalty,
+        boost: to_mint * (lock_tier.weight - Decimal::one()),
+        reward_index: boost.reward_per_weight,
+        boost_rewards: Uint128::zero(),
+        approvals: vec![],
+    };
+    boost.weight += position.boost;
+    BOOST.save(deps.storage, &boost)?;
+    let position_id = POSITION_COUNT.may_load(deps.storage)?.unwrap_or_default() + 1;
+    POSITION_COUNT.save(deps.storage, &position_id)?;
+    positions().save(deps.storage, position_id, &position)?;
+
+      // call into cw20-base to mint the token, call as self as no one else is allowed
+      let sub_info = MessageInfo {
+        sender: env.contract.address.clone(),
+        funds: vec![],
+    };
+    let holders = [sender.clone()];
+    with_rewards(deps.branch(), &holders, |deps| {
+        execute_mint(deps, env, sub_info, sender.to_string(), to_mint)
+    })?;
+
+    // bond them to the validator, the tax stays with the contract
+    let delegate = match &stake_info.delegation {
+        Some(delegation) => delegate_msgs(delegation, &stake_info.stake_denom, net),
+        None => vec![],
+    };
+    let res = Response::new()
+    .add_messages(delegate)
+    .add_attribute("action", "lock")
+    .add_attribute("from", sender)
+    .add_attribute("locked", lock_amount)
+    .add_attribute("tax", tax)
+    .add_attribute("minted", to_mint)
+    .add_attribute("position_id", position_id.to_string())
+    .add_attribute("tier", position.tier)
+    .add_attribute("release", position.release.to_string());
+    let res = if donated.is_zero() {
+        res
+    } else {
+        res.add_attribute("donated", donated)
+    };
+    Ok(res)
+
+}
+
+/// ReleasedPart is the share of an unlock taken from one position
+#[derive(Debug, PartialEq)]
+pub struct ReleasedPart {
+    /// None for tokens that were not backed by any position
+    pub position_id: Option<u64>,
+    pub amount: Uint128,
+    /// None for tokens that were not backed by any position
+    pub tier: Option<String>,
+    /// penalty recorded on the position
+    pub penalty: Decimal,
+    pub matured: bool,
+    /// when the position matured or will mature
+    pub release: Expiration,
+    /// boost rewards the position collected, paid out with the unlock
+    pub rewards: Uint128,
+}
+
+/// PositionSelection picks the positions an unlock consumes
+#[derive(Debug, PartialEq)]
+pub enum PositionSelection {
+    /// these positions of the owner, in the given order
+    Ids(Vec<u64>),
+    Strategy(UnlockStrategy),
+}
+
+impl PositionSelection {
+    fn new(
+        token_id: Option<String>,
+        position_ids: Option<Vec<u64>>,
+        strategy: Option<UnlockStrategy>,
+    ) -> Result<Self, ContractError> {
+        match (token_id, position_ids, strategy) {
+            (None, None, strategy) => Ok(Self::Strategy(strategy.unwrap_or_default())),
+            (Some(token_id), None, None) => Ok(Self::Ids(vec![parse_token_id(&token_id)?])),
+            (None, Some(ids), None) => Ok(Self::Ids(ids)),
+            _ => Err(ContractError::InvalidPositionSelection {}),
+        }
+    }
+}
+
+/// release_positions consumes `amount` derivative tokens out of a `balance` from the owner's
+/// positions, in the order given by `selection`.
+/// Tokens not backed by any position (held since before positions were recorded) are matured.
+fn release_positions(
+    storage: &mut dyn Storage,
+    owner: &Addr,
+    balance: Uint128,
+    amount: Uint128,
+    block: &BlockInfo,
+    boost: &mut Boost,
+    selection: PositionSelection,
+) -> Result<Vec<ReleasedPart>, ContractError> {
+    let mut owned: Vec<(u64, LockPosition)> = match &selection {
+        PositionSelection::Ids(ids) => {
+            let mut owned = vec![];
+            for &id in ids {
+                if owned.iter().any(|(other, _)| *other == id) {
+                    continue;
+                }
+                let position = load_position(storage, id)?;
+                if position.owner != *owner {
+                    return Err(ContractError::Unauthorized {});
+                }
+                owned.push((id, position));
+            }
+            let backed: Uint128 = owned.iter().map(|(_, p)| p.amount).sum();
+            if amount > backed {
+                return Err(ContractError::ExceedsPosition { amount: backed });
+            }
+            owned
+        }
+        PositionSelection::Strategy(_) => positions()
+            .idx
+            .owner
+            .prefix(owner.clone())
+            .range(storage, None, None, Order::Ascending)
+            .collect::<StdResult<_>>()?,
+    };
+
+    // tokens without a position are the oldest ones, they go first in FIFO order and
+    // before the unmatured positions when maturity comes first
+    let backed: Uint128 = owned.iter().map(|(_, p)| p.amount).sum();
+    let available = balance.saturating_sub(backed);
+    let mut unbacked = None;
+    match selection {
+        PositionSelection::Ids(_) => {}
+        PositionSelection::Strategy(UnlockStrategy::Fifo {}) => unbacked = Some((0, available)),
+        PositionSelection::Strategy(UnlockStrategy::Lifo {}) => owned.reverse(),
+        PositionSelection::Strategy(UnlockStrategy::MaturedFirst {}) => {
+            let (matured, mut unmatured): (Vec<_>, Vec<_>) = owned
+                .into_iter()
+                .partition(|(_, p)| p.release.is_expired(block));
+            unmatured.sort_by_key(|(_, p)| release_key(&p.release));
+            unbacked = Some((matured.len(), available));
+            owned = matured.into_iter().chain(unmatured).collect();
+        }
+    }
+
+    let mut parts = vec![];
+    let mut remaining = amount;
+    for (index, (id, mut position)) in owned.into_iter().enumerate() {
+        if let Some((at, available)) = unbacked {
+            if at == index {
+                let taken = remaining.min(available);
+                remaining -= taken;
+                if !taken.is_zero() {
+                    parts.push(unbacked_part(taken, block));
+                }
+            }
+        }
+        if remaining.is_zero() {
+            break;
+        }
+        let taken = remaining.min(position.amount);
+        remaining -= taken;
+
+        // collect the boost rewards and drop the boost of the unlocked part,
+        // the rewards of the rest stay with the position
+        let earned = earned_boost(&position, boost);
+        let rewards = earned.multiply_ratio(taken, position.amount);
+        let boost_taken = position.boost.multiply_ratio(taken, position.amount);
+        boost.pending -= rewards;
+        boost.weight -= boost_taken;
+        position.boost -= boost_taken;
+        position.boost_rewards = earned - rewards;
+        position.reward_index = boost.reward_per_weight;
+
+        parts.push(ReleasedPart {
+            position_id: Some(id),
+            amount: taken,
+            tier: Some(position.tier.clone()),
+            penalty: position.penalty,
+            matured: position.release.is_expired(block),
+            release: position.release,
+            rewards,
+        });
+
+        position.amount -= taken;
+        if position.amount.is_zero() {
+            positions().remove(storage, id)?;
+        } else {
+            positions().save(storage, id, &position)?;
+        }
+    }
+    if !remaining.is_zero() {
+        parts.push(unbacked_part(remaining, block));
+    }
+    Ok(parts)
+}
+
+/// unbacked_part releases tokens not backed by any position, they are always matured
+fn unbacked_part(amount: Uint128, block: &BlockInfo) -> ReleasedPart {
+    ReleasedPart {
+        position_id: None,
+        amount,
+        tier: None,
+        penalty: Decimal::zero(),
+        matured: true,
+        release: Expiration::AtTime(block.time),
+        rewards: Uint128::zero(),
+    }
+}
+
+/// release_key orders releases, height based ones before time based ones
+fn release_key(release: &Expiration) -> (u8, u64) {
+    match release {
+        Expiration::AtHeight(height) => (0, *height),
+        Expiration::AtTime(time) => (1, time.nanos()),
+        Expiration::Never {} => (2, 0),
+    }
+}
+
+
+pub fn unlock(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    amount: Uint128,
+    selection: PositionSelection,
+) -> Result<Response, ContractError> {
+    let balance = BALANCES
+        .may_load(deps.storage, &info.sender)?
+        .unwrap_or_default();
+
+    // burn from the original caller
+    let holders = [info.sender.clone()];
+    with_rewards(deps.branch(), &holders, |deps| {
+        execute_burn(deps, env.clone(), info.clone(), amount)
+    })?;
+
+    // re-calculate locked to ensure we have real values
+    // locked is the total number of qtum tokens users locked to this address
+    let locked = get_locked(deps.as_ref(), &env.contract.address)?;
+
+    accrue_rewards(deps.storage, &env.block)?;
+    let mut supply = TOTAL_SUPPLY.load(deps.storage)?;
+    // supply caches the (expected) results of get_locked() so we don't have expensive queries
+    // everywhere, this keeps it honest and takes care of tokens sent outside the hooks
+    let stake_info = STAKING_INFO.load(deps.storage)?;
+    let donated = reconcile_locks(
+        deps.storage,
+        &mut supply,
+        &stake_info,
+        locked,
+        Uint128::zero(),
+    )?;
+
+    // calculate tax and remainder to unlock, only positions exiting before they mature pay the penalty.
+    // burnt shares are worth their part of the locked tokens at the current exchange rate
+    let nominal_value = nominal_value(&supply);
+    let mut boost = BOOST.load(deps.storage)?;
+    let parts = release_positions(
+        deps.storage,
+        &info.sender,
+        balance,
+        amount,
+        &env.block,
+        &mut boost,
+        selection,
+    )?;
+    BOOST.save(deps.storage, &boost)?;
+    let mut value = Uint128::zero();
+    let mut tax = Uint128::zero();
+    let mut rewards = Uint128::zero();
+    let mut unlock = Uint128::zero();
+    let mut matured = Uint128::zero();
+    let mut unmatured = Uint128::zero();
+    let mut res = Response::new();
+    for part in parts {
+        let part_value = value_of(&supply, part.amount);
+        let part_tax = if part.matured {
+            matured += part.amount;
+            Uint128::zero()
+        } else {
+            unmatured += part.amount;
+            part_value * part.penalty
+        };
+        let part_unlock = part_value - part_tax;
+        value += part_value;
+        tax += part_tax;
+        rewards += part.rewards;
+        unlock += part_unlock + part.rewards;
+
+        if let (Some(id), Some(tier)) = (part.position_id, part.tier) {
+            res = res
+                .add_attribute("position_id", id.to_string())
+                .add_attribute("tier", tier)
+                .add_attribute("maturity", part.release.to_string())
+                .add_attribute("early_exit", (!part.matured).to_string())
+                .add_attribute("position_burnt", part.amount)
+                .add_attribute("position_value", part_value)
+                .add_attribute("position_penalty", part_tax);
+        }
+    }
+    // matured positions are free to go and an early exit paid the penalty for it, so the tokens
+    // are released right away. Delegated tokens (the penalty included) are back after the
+    // unbonding period, whatever unit the positions matured in
+    let release = match &stake_info.delegation {
+        Some(delegation) => {
+            let undelegate = undelegate_msgs(
+                deps.as_ref(),
+                &env.contract.address,
+                &stake_info.stake_denom,
+                value,
+            )?;
+            res = res.add_messages(undelegate);
+            delegation.unbonding_period.after(&env.block)
+        }
+        None => Expiration::AtTime(env.block.time),
+    };
+    CLAIMS.create_claim(deps.storage, &info.sender, unlock, release)?;
+
+    // the unlocked tokens stay on the contract until they are claimed
+    supply.locked = supply
+        .locked
+        .checked_sub(value)
+        .map_err(StdError::overflow)?;
+    supply.claims += unlock;
+    supply.issued = supply
+        .issued
+        .checked_sub(amount)
+        .map_err(StdError::overflow)?;
+    add_fees(deps.storage, &env, &stake_info, &mut supply, tax)?;
+    TOTAL_SUPPLY.save(deps.storage, &supply)?;
+    FEES.update(deps.storage, |mut fees| -> StdResult<_> {
+        fees.penalty += tax;
+        Ok(fees)
+    })?;
+
+    let res = res
+        .add_attribute("action", "unlock")
+        .add_attribute("to", info.sender)
+        .add_attribute("unlocked", unlock)
+        .add_attribute("burnt", amount)
+        .add_attribute("nominal_value", nominal_value.to_string())
+        .add_attribute("penalty", tax)
+        .add_attribute("boost_rewards", rewards)
+        .add_attribute("matured", matured)
+        .add_attribute("unmatured", unmatured);
+    let res = if donated.is_zero() {
+        res
+    } else {
+        res.add_attribute("donated", donated)
+    };
+    Ok(res)
+}
+
+pub fn extend_lock(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    position_id: u64,
+    tier: String,
+) -> Result<Response, ContractError> {
+    let stake_info = STAKING_INFO.load(deps.storage)?;
+    let mut position = load_position(deps.storage, position_id)?;
+    if position.owner != info.sender {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    // the new terms are compared with the ones the position recorded, its tier may have
+    // changed or been removed since
+    let lock_tier = load_tier(deps.storage, &tier)?;
+    let release = lock_tier.period.after(&env.block);
+    if is_shorter(&lock_tier.period, &position.period)
+        || release.partial_cmp(&position.release) == Some(Ordering::Less)
+    {
+        return Err(ContractError::CannotShortenLock {});
+    }
+    if lock_tier.tax < position.tax {
+        return Err(ContractError::CannotLowerTax {});
+    }
+
+    // the upgrade pays the tax difference on what the position is worth now,
+    // with derivative tokens of the position, rounded in favor of the pool
+    accrue_rewards(deps.storage, &env.block)?;
+    let mut supply = TOTAL_SUPPLY.load(deps.storage)?;
+    let tax = value_of(&supply, position.amount) * (lock_tier.tax - position.tax);
+    let burnt = shares_to_cover(&supply, tax);
+    if !burnt.is_zero() {
+        let holders = [info.sender.clone()];
+        let sub_info = info.clone();
+        with_rewards(deps.branch(), &holders, |deps| {
+            execute_burn(deps, env.clone(), sub_info, burnt)
+        })?;
+    }
+    supply.locked -= tax;
+    supply.issued -= burnt;
+    add_fees(deps.storage, &env, &stake_info, &mut supply, tax)?;
+    FEES.update(deps.storage, |mut fees| -> StdResult<_> {
+        fees.tax += tax;
+        Ok(fees)
+    })?;
+
+    // boost rewards earned under the old weight are released like on unlock
+    let mut boost = BOOST.load(deps.storage)?;
+    let rewards = earned_boost(&position, &boost);
+    if !rewards.is_zero() {
+        CLAIMS.create_claim(
+            deps.storage,
+            &info.sender,
+            rewards,
+            Expiration::AtTime(env.block.time),
+        )?;
+        boost.pending -= rewards;
+        supply.claims += rewards;
+    }
+    TOTAL_SUPPLY.save(deps.storage, &supply)?;
+
+    position.amount -= burnt;
+    boost.weight -= position.boost;
+    position.boost = position.amount * (lock_tier.weight - Decimal::one());
+    boost.weight += position.boost;
+    position.boost_rewards = Uint128::zero();
+    position.reward_index = boost.reward_per_weight;
+    BOOST.save(deps.storage, &boost)?;
+
+    position.tier = tier.clone();
+    position.period = lock_tier.period;
+    position.tax = lock_tier.tax;
+    position.weight = lock_tier.weight;
+    position.release = release;
+    position.penalty = lock_tier.penalty;
+    positions().save(deps.storage, position_id, &position)?;
+
+    // the tax leaves the delegation, and joins the other fees once it is unbonded
+    let undelegate = match &stake_info.delegation {
+        Some(_) if !tax.is_zero() => undelegate_msgs(
+            deps.as_ref(),
+            &env.contract.address,
+            &stake_info.stake_denom,
+            tax,
+        )?,
+        _ => vec![],
+    };
+
+    let res = Response::new()
+        .add_messages(undelegate)
+        .add_attribute("action", "extend_lock")
+        .add_attribute("from", info.sender)
+        .add_attribute("position_id", position_id.to_string())
+        .add_attribute("tier", tier)
+        .add_attribute("release", release.to_string())
+        .add_attribute("tax", tax)
+        .add_attribute("burnt", burnt)
+        .add_attribute("boost_rewards", rewards);
+    Ok(res)
+}
+
+// add_fees books penalties and taxes taken out of the pool. Delegated ones are undelegated
+// with the rest and wait as a claim of the contract itself until the unbonding period is over
+fn add_fees(
+    storage: &mut dyn Storage,
+    env: &Env,
+    stake_info: &StakingInfo,
+    supply: &mut Supply,
+    amount: Uint128,
+) -> StdResult<()> {
+    match &stake_info.delegation {
+        Some(_) if amount.is_zero() => Ok(()),
+        Some(delegation) => CLAIMS.create_claim(
+            storage,
+            &env.contract.address,
+            amount,
+            delegation.unbonding_period.after(&env.block),
+        ),
+        None => {
+            supply.fees += amount;
+            Ok(())
+        }
+    }
+}
+
+// release_fees makes the fees that finished unbonding available
+fn release_fees(storage: &mut dyn Storage, env: &Env, supply: &mut Supply) -> StdResult<()> {
+    supply.fees += CLAIMS.claim_tokens(storage, &env.contract.address, &env.block, None)?;
+    Ok(())
+}
+
+// is_shorter compares periods of the same unit, periods in blocks and seconds can't be compared
+fn is_shorter(period: &Duration, other: &Duration) -> bool {
+    match (period, other) {
+        (Duration::Time(period), Duration::Time(other)) => period < other,
+        (Duration::Height(period), Duration::Height(other)) => period < other,
+        _ => false,
+    }
+}
+
+pub fn claim(deps: DepsMut, env: Env, info: MessageInfo) -> Result<Response, ContractError> {
+    let stake_info = STAKING_INFO.load(deps.storage)?;
+
+    let to_send = CLAIMS.claim_tokens(deps.storage, &info.sender, &env.block, None)?;
+    if to_send.is_zero() {
+        return Err(ContractError::NothingToClaim {});
+    }
+
+    let mut supply = TOTAL_SUPPLY.load(deps.storage)?;
+    supply.claims = supply
+        .claims
+        .checked_sub(to_send)
+        .map_err(StdError::overflow)?;
+    TOTAL_SUPPLY.save(deps.storage, &supply)?;
+
+    // send the released staking tokens back to the caller
+    let transfer = send_stake(deps.api, &stake_info, &info.sender, to_send)?;
+
+    let res = Response::new()
+        .add_message(transfer)
+        .add_attribute("action", "claim")
+        .add_attribute("from", info.sender)
+        .add_attribute("amount", to_send);
+    Ok(res)
+}
+
+
+pub fn withdraw_fees(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    recipient: String,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    let stake_info = STAKING_INFO.load(deps.storage)?;
+    if info.sender != stake_info.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    if amount.is_zero() {
+        return Err(ContractError::InvalidZeroAmount {});
+    }
+    let recipient = deps.api.addr_validate(&recipient)?;
+
+    let mut supply = TOTAL_SUPPLY.load(deps.storage)?;
+    release_fees(deps.storage, &env, &mut supply)?;
+    if amount > supply.fees {
+        return Err(ContractError::InsufficientFees {
+            available: supply.fees,
+        });
+    }
+    supply.fees -= amount;
+    TOTAL_SUPPLY.save(deps.storage, &supply)?;
+
+    let transfer = send_stake(deps.api, &stake_info, &recipient, amount)?;
+
+    let res = Response::new()
+        .add_message(transfer)
+        .add_attribute("action", "withdraw_fees")
+        .add_attribute("to", recipient)
+        .add_attribute("amount", amount);
+    Ok(res)
+}
+
+pub fn sweep(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    token: SweepToken,
+    recipient: String,
+) -> Result<Response, ContractError> {
+    let stake_info = STAKING_INFO.load(deps.storage)?;
+    if info.sender != stake_info.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    let recipient = deps.api.addr_validate(&recipient)?;
+
+    let (msg, token, amount): (CosmosMsg, String, Uint128) = match token {
+        SweepToken::Cw20 { address } => {
+            let address = deps.api.addr_validate(&address)?;
+            let canonical = deps.api.addr_canonicalize(address.as_str())?;
+            let balance = query_cw20_balance(deps.as_ref(), &address, &env.contract.address)?;
+
+            // what is owed in this token can't be swept: locked tokens, claims, fees and
+            // rewards for the staking token, unclaimed rewards for the reward token
+            let owed = if stake_info.staking_mode == (StakingMode::Cw20 {})
+                && canonical == stake_info.staking_token_address
+            {
+                tracked_balance(deps.storage, &TOTAL_SUPPLY.load(deps.storage)?)?
+            } else if Some(canonical) == stake_info.reward_token_address {
+                REWARD_POOL.load(deps.storage)?.pending
+            } else {
+                Uint128::zero()
+            };
+            let amount = balance.saturating_sub(owed);
+
+            let msg = WasmMsg::Execute {
+                contract_addr: address.to_string(),
+                msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                    recipient: recipient.to_string(),
+                    amount,
+                })?,
+                funds: vec![],
+            };
+            (msg.into(), address.to_string(), amount)
+        }
+        SweepToken::Native { denom } => {
+            let is_stake = stake_info.staking_mode == (StakingMode::Native {})
+                && denom == stake_info.stake_denom;
+            // when delegating, staking coins beyond claims and fees are rewards waiting
+            // to be reinvested for the holders
+            if is_stake && stake_info.delegation.is_some() {
+                return Err(ContractError::CannotSweepStake {});
+            }
+            let balance = deps.querier.query_balance(&env.contract.address, &denom)?;
+
+            // in native mode the staking coins are owed like the cw20 is in cw20 mode
+            let owed = if is_stake {
+                tracked_balance(deps.storage, &TOTAL_SUPPLY.load(deps.storage)?)?
+            } else {
+                Uint128::zero()
+            };
+            let amount = balance.amount.saturating_sub(owed);
+
+            let msg = BankMsg::Send {
+                to_address: recipient.to_string(),
+                amount: vec![coin(amount.u128(), &denom)],
+            };
+            (msg.into(), denom, amount)
+        }
+    };
+    if amount.is_zero() {
+        return Err(ContractError::NothingToSweep {});
+    }
+
+    let res = Response::new()
+        .add_message(msg)
+        .add_attribute("action", "sweep")
+        .add_attribute("token", token)
+        .add_attribute("to", recipient)
+        .add_attribute("amount", amount);
+    Ok(res)
+}
+
+pub fn set_tier(
+    deps: DepsMut,
+    info: MessageInfo,
+    config: TierConfig,
+) -> Result<Response, ContractError> {
+    let stake_info = STAKING_INFO.load(deps.storage)?;
+    if info.sender != stake_info.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let id = config.id.clone();
+    let old = TIERS.may_load(deps.storage, &id)?;
+    let tier = save_tier(deps.storage, config)?;
+    let mut res = Response::new()
+        .add_attribute("action", "set_tier")
+        .add_attribute("tier", id)
+        .add_attribute("replaced", old.is_some().to_string())
+        .add_attribute("period", tier.period.to_string())
+        .add_attribute("tax", tier.tax.to_string())
+        .add_attribute("penalty", tier.penalty.to_string())
+        .add_attribute("weight", tier.weight.to_string());
+    if let Some(old) = old {
+        res = res
+            .add_attribute("old_period", old.period.to_string())
+            .add_attribute("old_tax", old.tax.to_string())
+            .add_attribute("old_penalty", old.penalty.to_string())
+            .add_attribute("old_weight", old.weight.to_string());
+    }
+    Ok(res)
+}
+
+pub fn remove_tier(deps: DepsMut, info: MessageInfo, id: String) -> Result<Response, ContractError> {
+    let stake_info = STAKING_INFO.load(deps.storage)?;
+    if info.sender != stake_info.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    load_tier(deps.storage, &id)?;
+    TIERS.remove(deps.storage, &id);
+    let res = Response::new()
+        .add_attribute("action", "remove_tier")
+        .add_attribute("tier", id);
+    Ok(res)
+}
+
+pub fn update_config(
+    deps: DepsMut,
+    info: MessageInfo,
+    reward_token_address: Option<String>,
+    surplus_policy: Option<SurplusPolicy>,
+    lock_transfers: Option<bool>,
+) -> Result<Response, ContractError> {
+    let mut stake_info = STAKING_INFO.load(deps.storage)?;
+    if info.sender != stake_info.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let mut res = Response::new().add_attribute("action", "update_config");
+    if let Some(reward_token) = reward_token_address {
+        // the accumulator is denominated in one token, it cannot be switched later
+        if stake_info.reward_token_address.is_some() {
+            return Err(ContractError::RewardTokenAlreadySet {});
+        }
+        let reward_token = deps.api.addr_validate(&reward_token)?;
+        stake_info.reward_token_address =
+            Some(deps.api.addr_canonicalize(reward_token.as_str())?);
+        res = res.add_attribute("new_reward_token", reward_token);
+    }
+    if let Some(policy) = surplus_policy {
+        res = res
+            .add_attribute("old_surplus_policy", stake_info.surplus_policy.to_string())
+            .add_attribute("new_surplus_policy", policy.to_string());
+        stake_info.surplus_policy = policy;
+    }
+    if let Some(lock_transfers) = lock_transfers {
+        res = res
+            .add_attribute("old_lock_transfers", stake_info.lock_transfers.to_string())
+            .add_attribute("new_lock_transfers", lock_transfers.to_string());
+        stake_info.lock_transfers = lock_transfers;
+    }
+
+    STAKING_INFO.save(deps.storage, &stake_info)?;
+    Ok(res)
+}
+
+pub fn propose_new_owner(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    owner: String,
+    expiry: Option<Expiration>,
+) -> Result<Response, ContractError> {
+    let stake_info = STAKING_INFO.load(deps.storage)?;
+    if info.sender != stake_info.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    let owner = deps.api.addr_validate(&owner)?;
+    if owner == stake_info.owner {
+        return Err(ContractError::CannotSetOwnAccount {});
+    }
+    if let Some(expiry) = expiry {
+        if expiry.is_expired(&env.block) {
+            return Err(ContractError::InvalidExpiration {});
+        }
+    }
+
+    PENDING_OWNER.save(
+        deps.storage,
+        &PendingOwner {
+            owner: owner.clone(),
+            expiry,
+        },
+    )?;
+
+    let res = Response::new()
+        .add_attribute("action", "propose_new_owner")
+        .add_attribute("owner", stake_info.owner)
+        .add_attribute("pending_owner", owner);
+    Ok(res)
+}
+
+pub fn accept_ownership(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let pending = PENDING_OWNER
+        .may_load(deps.storage)?
+        .ok_or(ContractError::NoPendingOwner {})?;
+    if info.sender != pending.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    if let Some(expiry) = pending.expiry {
+        if expiry.is_expired(&env.block) {
+            return Err(ContractError::OwnershipProposalExpired {});
+        }
+    }
+
+    let mut stake_info = STAKING_INFO.load(deps.storage)?;
+    let previous = stake_info.owner;
+    stake_info.owner = pending.owner;
+    STAKING_INFO.save(deps.storage, &stake_info)?;
+    PENDING_OWNER.remove(deps.storage);
+
+    let res = Response::new()
+        .add_attribute("action", "accept_ownership")
+        .add_attribute("previous_owner", previous)
+        .add_attribute("owner", stake_info.owner);
+    Ok(res)
+}
+
+pub fn cancel_ownership_proposal(
+    deps: DepsMut,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let stake_info = STAKING_INFO.load(deps.storage)?;
+    if info.sender != stake_info.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    if PENDING_OWNER.may_load(deps.storage)?.is_none() {
+        return Err(ContractError::NoPendingOwner {});
+    }
+    PENDING_OWNER.remove(deps.storage);
+
+    Ok(Response::new().add_attribute("action", "cancel_ownership_proposal"))
+}
+
+// token ids of the position receipts are the position ids
+fn parse_token_id(token_id: &str) -> Result<u64, ContractError> {
+    token_id.parse().map_err(|_| ContractError::PositionNotFound {
+        token_id: token_id.to_string(),
+    })
+}
+
+fn load_position(storage: &dyn Storage, id: u64) -> Result<LockPosition, ContractError> {
+    positions()
+        .may_load(storage, id)?
+        .ok_or(ContractError::PositionNotFound {
+            token_id: id.to_string(),
+        })
+}
+
+// the owner, an approved spender or an operator of the owner can move a position
+fn check_can_send(
+    deps: Deps,
+    block: &BlockInfo,
+    sender: &Addr,
+    position: &LockPosition,
+) -> Result<(), ContractError> {
+    if position.owner == *sender {
+        return Ok(());
+    }
+    if position
+        .approvals
+        .iter()
+        .any(|approval| approval.spender == *sender && !approval.is_expired(block))
+    {
+        return Ok(());
+    }
+    check_operator(deps, block, sender, &position.owner)
+}
+
+// the owner or an operator of the owner can approve spenders
+fn check_operator(
+    deps: Deps,
+    block: &BlockInfo,
+    sender: &Addr,
+    owner: &Addr,
+) -> Result<(), ContractError> {
+    if sender == owner {
+        return Ok(());
+    }
+    match OPERATORS.may_load(deps.storage, (owner, sender))? {
+        Some(expires) if !expires.is_expired(block) => Ok(()),
+        _ => Err(ContractError::Unauthorized {}),
+    }
+}
+
+// move_position hands the position, and the derivative tokens it backs, to the recipient
+fn move_position(
+    deps: DepsMut,
+    env: &Env,
+    sender: &Addr,
+    recipient: &Addr,
+    token_id: &str,
+) -> Result<(), ContractError> {
+    let id = parse_token_id(token_id)?;
+    let mut position = load_position(deps.storage, id)?;
+    check_can_send(deps.as_ref(), &env.block, sender, &position)?;
+
+    // with transfers locked, unmatured receipts stay with their owner, and the tokens left with
+    // the owner must still back the other unmatured positions
+    if STAKING_INFO.load(deps.storage)?.lock_transfers {
+        if !position.release.is_expired(&env.block) {
+            return Err(ContractError::UnmaturedPosition {
+                token_id: token_id.to_string(),
+            });
+        }
+        let balance = BALANCES
+            .may_load(deps.storage, &position.owner)?
+            .unwrap_or_default();
+        let transferable =
+            balance.saturating_sub(unmatured_amount(deps.storage, &env.block, &position.owner)?);
+        if position.amount > transferable {
+            return Err(ContractError::LockedBalance { transferable });
+        }
+    }
+
+    let holders = [position.owner.clone(), recipient.clone()];
+    settle_rewards(deps.storage, &holders)?;
+    BALANCES.update(deps.storage, &position.owner, |balance| -> StdResult<_> {
+        balance
+            .unwrap_or_default()
+            .checked_sub(position.amount)
+            .map_err(StdError::overflow)
+    })?;
+    BALANCES.update(deps.storage, recipient, |balance| -> StdResult<_> {
+        Ok(balance.unwrap_or_default() + position.amount)
+    })?;
+    sync_reward_debts(deps.storage, &holders)?;
+
+    position.owner = recipient.clone();
+    position.approvals = vec![];
+    positions().save(deps.storage, id, &position)?;
+    Ok(())
+}
+
+pub fn transfer_nft(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    recipient: String,
+    token_id: String,
+) -> Result<Response, ContractError> {
+    let recipient = deps.api.addr_validate(&recipient)?;
+    move_position(deps, &env, &info.sender, &recipient, &token_id)?;
+
+    let res = Response::new()
+        .add_attribute("action", "transfer_nft")
+        .add_attribute("sender", info.sender)
+        .add_attribute("recipient", recipient)
+        .add_attribute("token_id", token_id);
+    Ok(res)
+}
+
+pub fn send_nft(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    contract: String,
+    token_id: String,
+    msg: Binary,
+) -> Result<Response, ContractError> {
+    let contract = deps.api.addr_validate(&contract)?;
+    move_position(deps, &env, &info.sender, &contract, &token_id)?;
+
+    let receive = Cw721ReceiveMsg {
+        sender: info.sender.to_string(),
+        token_id: token_id.clone(),
+        msg,
+    };
+    let res = Response::new()
+        .add_message(receive.into_cosmos_msg(contract.clone())?)
+        .add_attribute("action", "send_nft")
+        .add_attribute("sender", info.sender)
+        .add_attribute("recipient", contract)
+        .add_attribute("token_id", token_id);
+    Ok(res)
+}
+
+pub fn approve(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    spender: String,
+    token_id: String,
+    expires: Option<Expiration>,
+) -> Result<Response, ContractError> {
+    let id = parse_token_id(&token_id)?;
+    let mut position = load_position(deps.storage, id)?;
+    check_operator(deps.as_ref(), &env.block, &info.sender, &position.owner)?;
+
+    let expires = expires.unwrap_or_default();
+    if expires.is_expired(&env.block) {
+        return Err(ContractError::Expired {});
+    }
+    let spender = deps.api.addr_validate(&spender)?;
+    position.approvals.retain(|approval| approval.spender != spender);
+    position.approvals.push(PositionApproval {
+        spender: spender.clone(),
+        expires,
+    });
+    positions().save(deps.storage, id, &position)?;
+
+    let res = Response::new()
+        .add_attribute("action", "approve")
+        .add_attribute("sender", info.sender)
+        .add_attribute("spender", spender)
+        .add_attribute("token_id", token_id);
+    Ok(res)
+}
+
+pub fn revoke(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    spender: String,
+    token_id: String,
+) -> Result<Response, ContractError> {
+    let id = parse_token_id(&token_id)?;
+    let mut position = load_position(deps.storage, id)?;
+    check_operator(deps.as_ref(), &env.block, &info.sender, &position.owner)?;
+
+    let spender = deps.api.addr_validate(&spender)?;
+    position.approvals.retain(|approval| approval.spender != spender);
+    positions().save(deps.storage, id, &position)?;
+
+    let res = Response::new()
+        .add_attribute("action", "revoke")
+        .add_attribute("sender", info.sender)
+        .add_attribute("spender", spender)
+        .add_attribute("token_id", token_id);
+    Ok(res)
+}
+
+pub fn approve_all(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    operator: String,
+    expires: Option<Expiration>,
+) -> Result<Response, ContractError> {
+    let expires = expires.unwrap_or_default();
+    if expires.is_expired(&env.block) {
+        return Err(ContractError::Expired {});
+    }
+    let operator = deps.api.addr_validate(&operator)?;
+    OPERATORS.save(deps.storage, (&info.sender, &operator), &expires)?;
+
+    let res = Response::new()
+        .add_attribute("action", "approve_all")
+        .add_attribute("sender", info.sender)
+        .add_attribute("operator", operator);
+    Ok(res)
+}
+
+pub fn revoke_all(
+    deps: DepsMut,
+    info: MessageInfo,
+    operator: String,
+) -> Result<Response, ContractError> {
+    let operator = deps.api.addr_validate(&operator)?;
+    OPERATORS.remove(deps.storage, (&info.sender, &operator));
+
+    let res = Response::new()
+        .add_attribute("action", "revoke_all")
+        .add_attribute("sender", info.sender)
+        .add_attribute("operator", operator);
+    Ok(res)
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(mut deps: DepsMut, env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
+    let stored = get_contract_version(deps.storage)?;
+    if stored.contract != CONTRACT_NAME {
+        return Err(ContractError::CannotMigrate {
+            stored: stored.contract,
+            current: CONTRACT_NAME.to_string(),
+        });
+    }
+
+    // never go back to an older version
+    let stored_version: Version = stored.version.parse()?;
+    let current_version: Version = CONTRACT_VERSION.parse()?;
+    if stored_version > current_version {
+        return Err(ContractError::CannotMigrate {
+            stored: stored.version,
+            current: CONTRACT_VERSION.to_string(),
+        });
+    }
+
+    run_migrations(deps.branch(), &env, &stored_version)?;
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    let res = Response::new()
+        .add_attribute("action", "migrate")
+        .add_attribute("from_version", stored.version)
+        .add_attribute("to_version", CONTRACT_VERSION);
+    Ok(res)
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
+    match msg {
+       
+        QueryMsg::Investment {} => to_binary(&query_investment(deps, env)?),
+        QueryMsg::Tiers {} => to_binary(&query_tiers(deps)?),
+        QueryMsg::Positions {
+            address,
+            start_after,
+            limit,
+        } => to_binary(&query_positions(deps, env, address, start_after, limit)?),
+        QueryMsg::Ownership {} => to_binary(&query_ownership(deps)?),
+        QueryMsg::Fees {} => to_binary(&query_fees(deps, env)?),
+        QueryMsg::TransferableBalance { address } => {
+            to_binary(&query_transferable_balance(deps, env, address)?)
+        }
+        QueryMsg::PendingRewards { address } => {
+            to_binary(&query_pending_rewards(deps, address)?)
+        }
+        QueryMsg::Claims { address } => {
+            to_binary(&CLAIMS.query_claims(deps, &deps.api.addr_validate(&address)?)?)
+        }
+        // inherited from cw20-base
+        QueryMsg::TokenInfo {} => to_binary(&query_token_info(deps)?),
+        QueryMsg::Balance { address } => to_binary(&query_balance(deps, address)?),
+        QueryMsg::Allowance { owner, spender } => {
+            to_binary(&query_allowance(deps, owner, spender)?)
+        }
+        // cw721 interface of the position receipts
+        QueryMsg::OwnerOf {
+            token_id,
+            include_expired,
+        } => to_binary(&query_owner_of(
+            deps,
+            env,
+            token_id,
+            include_expired.unwrap_or(false),
+        )?),
+        QueryMsg::NumTokens {} => to_binary(&query_num_tokens(deps)?),
+        QueryMsg::ContractInfo {} => to_binary(&query_nft_contract_info(deps)?),
+        QueryMsg::NftInfo { token_id } => to_binary(&query_nft_info(deps, token_id)?),
+        QueryMsg::AllNftInfo {
+            token_id,
+            include_expired,
+        } => to_binary(&AllNftInfoResponse {
+            access: query_owner_of(deps, env, token_id.clone(), include_expired.unwrap_or(false))?,
+            info: query_nft_info(deps, token_id)?,
+        }),
+        QueryMsg::Tokens {
+            owner,
+            start_after,
+            limit,
+        } => to_binary(&query_tokens(deps, Some(owner), start_after, limit)?),
+        QueryMsg::AllTokens { start_after, limit } => {
+            to_binary(&query_tokens(deps, None, start_after, limit)?)
+        }
+    }
+}
+
+fn query_position(deps: Deps, token_id: &str) -> StdResult<LockPosition> {
+    token_id
+        .parse()
+        .ok()
+        .and_then(|id: u64| positions().may_load(deps.storage, id).transpose())
+        .unwrap_or_else(|| Err(StdError::not_found("position")))
+}
+
+pub fn query_owner_of(
+    deps: Deps,
+    env: Env,
+    token_id: String,
+    include_expired: bool,
+) -> StdResult<OwnerOfResponse> {
+    let position = query_position(deps, &token_id)?;
+    let approvals = position
+        .approvals
+        .into_iter()
+        .filter(|approval| include_expired || !approval.is_expired(&env.block))
+        .map(|approval| Approval {
+            spender: approval.spender.to_string(),
+            expires: approval.expires,
+        })
+        .collect();
+    Ok(OwnerOfResponse {
+        owner: position.owner.to_string(),
+        approvals,
+    })
+}
+
+pub fn query_nft_info(deps: Deps, token_id: String) -> StdResult<NftInfoResponse> {
+    let position = query_position(deps, &token_id)?;
+    Ok(NftInfoResponse {
+        token_uri: None,
+        extension: PositionReceipt {
+            amount: position.amount,
+            tier: position.tier,
+            start: position.start,
+            release: position.release,
+        },
+    })
+}
+
+pub fn query_num_tokens(deps: Deps) -> StdResult<NumTokensResponse> {
+    let count = positions()
+        .keys(deps.storage, None, None, Order::Ascending)
+        .count() as u64;
+    Ok(NumTokensResponse { count })
+}
+
+pub fn query_nft_contract_info(deps: Deps) -> StdResult<NftContractInfoResponse> {
+    let info = TOKEN_INFO.load(deps.storage)?;
+    Ok(NftContractInfoResponse {
+        name: info.name,
+        symbol: info.symbol,
+    })
+}
+
+pub fn query_tokens(
+    deps: Deps,
+    owner: Option<String>,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<TokensResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start_after = start_after
+        .map(|token_id| {
+            token_id
+                .parse::<u64>()
+                .map_err(|_| StdError::generic_err("invalid token_id"))
+        })
+        .transpose()?;
+    let start = start_after.map(Bound::exclusive);
+
+    let ids = match owner {
+        Some(owner) => {
+            let owner = deps.api.addr_validate(&owner)?;
+            positions()
+                .idx
+                .owner
+                .prefix(owner)
+                .keys(deps.storage, start, None, Order::Ascending)
+                .take(limit)
+                .collect::<StdResult<Vec<_>>>()?
+        }
+        None => positions()
+            .keys(deps.storage, start, None, Order::Ascending)
+            .take(limit)
+            .collect::<StdResult<Vec<_>>>()?,
+    };
+    Ok(TokensResponse {
+        tokens: ids.iter().map(u64::to_string).collect(),
+    })
+}
+
+pub fn query_transferable_balance(
+    deps: Deps,
+    env: Env,
+    address: String,
+) -> StdResult<TransferableBalanceResponse> {
+    let address = deps.api.addr_validate(&address)?;
+    let locked = locked_balance(deps, &env.block, &address)?;
+    let balance = BALANCES.may_load(deps.storage, &address)?.unwrap_or_default();
+    Ok(TransferableBalanceResponse {
+        transferable: balance - locked,
+        locked,
+    })
+}
+
+pub fn query_pending_rewards(deps: Deps, address: String) -> StdResult<PendingRewardsResponse> {
+    let address = deps.api.addr_validate(&address)?;
+    let stake_info = STAKING_INFO.load(deps.storage)?;
+    let pool = REWARD_POOL.may_load(deps.storage)?.unwrap_or_default();
+    let balance = BALANCES.may_load(deps.storage, &address)?.unwrap_or_default();
+    let user = USER_REWARDS.may_load(deps.storage, &address)?.unwrap_or_default();
+
+    Ok(PendingRewardsResponse {
+        pending: user.pending + balance * pool.reward_per_share - user.reward_debt,
+        reward_token: stake_info
+            .reward_token_address
+            .map(|addr| deps.api.addr_humanize(&addr))
+            .transpose()?
+            .map(|addr| addr.to_string()),
+    })
+}
+
+pub fn query_investment(deps: Deps, env: Env) -> StdResult<InvestmentResponse> {
+    let stake_info = STAKING_INFO.load(deps.storage)?;
+    let mut supply = TOTAL_SUPPLY.load(deps.storage)?;
+
+    // include what the schedule has released since it was last accrued
+    let (reward_rate, apr) = match REWARD_SCHEDULE.may_load(deps.storage)? {
+        Some(mut schedule) => {
+            let mut boost = BOOST.load(deps.storage)?;
+            accrue_schedule(&mut supply, &mut boost, &mut schedule, env.block.time);
+            let rate = schedule.rate(env.block.time);
+            // weight one only gets its share of the rate
+            let apr = if supply.locked.is_zero() {
+                Decimal::zero()
+            } else {
+                rate * Decimal::from_ratio(SECONDS_PER_YEAR, supply.locked)
+                    * Decimal::from_ratio(supply.issued, supply.issued + boost.weight)
+            };
+            (rate, apr)
+        }
+        None => (Decimal::zero(), Decimal::zero()),
+    };
+
+    let res = InvestmentResponse {
+        owner: stake_info.owner.to_string(),
+        token_supply: supply.issued,
+        staked_tokens: coin(supply.locked.u128(), &stake_info.stake_denom),
+        nominal_value: nominal_value(&supply),
+        reward_rate,
+        apr,
+        surplus_policy: stake_info.surplus_policy,
+        lock_transfers: stake_info.lock_transfers,
+        staking_mode: stake_info.staking_mode,
+        delegation: stake_info.delegation,
+    };
+    Ok(res)
+}
+
+pub fn query_tiers(deps: Deps) -> StdResult<TiersResponse> {
+    let tiers = TIERS
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| {
+            item.map(|(id, tier)| TierResponse {
+                id,
+                period: tier.period,
+                tax: tier.tax,
+                penalty: tier.penalty,
+                weight: tier.weight,
+            })
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(TiersResponse { tiers })
+}
+
+pub fn query_ownership(deps: Deps) -> StdResult<OwnershipResponse> {
+    let stake_info = STAKING_INFO.load(deps.storage)?;
+    let pending = PENDING_OWNER.may_load(deps.storage)?;
+    Ok(OwnershipResponse {
+        owner: stake_info.owner.to_string(),
+        pending_owner: pending.as_ref().map(|p| p.owner.to_string()),
+        pending_expiry: pending.and_then(|p| p.expiry),
+    })
+}
+
+pub fn query_fees(deps: Deps, env: Env) -> StdResult<FeesResponse> {
+    let fees = FEES.load(deps.storage)?;
+    let supply = TOTAL_SUPPLY.load(deps.storage)?;
+    // delegated fees wait as claims of the contract, the released ones can be withdrawn
+    let claims = CLAIMS.query_claims(deps, &env.contract.address)?.claims;
+    let (released, unbonding): (Vec<_>, Vec<_>) = claims
+        .into_iter()
+        .partition(|claim| claim.release_at.is_expired(&env.block));
+    Ok(FeesResponse {
+        tax: fees.tax,
+        penalty: fees.penalty,
+        available: supply.fees + released.iter().map(|claim| claim.amount).sum::<Uint128>(),
+        unbonding: unbonding.iter().map(|claim| claim.amount).sum(),
+    })
+}
+
+pub fn query_positions(
+    deps: Deps,
+    env: Env,
+    address: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<PositionsResponse> {
+    let owner = deps.api.addr_validate(&address)?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+
+    // include the boost released since the schedule was last accrued
+    let mut boost = BOOST.load(deps.storage)?;
+    if let Some(mut schedule) = REWARD_SCHEDULE.may_load(deps.storage)? {
+        let mut supply = TOTAL_SUPPLY.load(deps.storage)?;
+        accrue_schedule(&mut supply, &mut boost, &mut schedule, env.block.time);
+    }
+
+    let positions = positions()
+        .idx
+        .owner
+        .prefix(owner)
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            item.map(|(id, position)| PositionResponse {
+                id,
+                amount: position.amount,
+                matured: position.release.is_expired(&env.block),
+                pending_rewards: earned_boost(&position, &boost),
+                boost: position.boost,
+                tier: position.tier,
+                start: position.start,
+                release: position.release,
+            })
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(PositionsResponse { positions })
+}
+
+
+#[cfg(test)]
 mod tests {
 
-    use super::*;
+    use super::*;
+
+    use cosmwasm_std::testing::{
+         mock_dependencies_with_balance, mock_env, mock_info, MockApi, MockQuerier, MockStorage,
+         MOCK_CONTRACT_ADDR,
+    };
+    use cw20_base::state::BALANCES;
+    use cw_utils::PaymentError;
+    use cw_controllers::ClaimsResponse;
+    use cw_multi_test::{
+        App, ContractWrapper, Executor, StakingInfo as MultiTestStakingInfo, StakingSudo,
+    };
+    use crate::msg::{DelegationConfig, Rate, TierConfig};
+    use cosmwasm_std::{
+        coins, ContractResult, OwnedDeps, Validator, SubMsg, SystemError, SystemResult, WasmQuery,
+    };
+
+ 
+ 
+    fn default_instantiate() -> InstantiateMsg {
+        InstantiateMsg {
+            name: "xQtum".to_string(),
+            symbol: "xQtum".to_string(),
+            decimals: 6,
+            tiers: default_tiers(),
+            reward_token_address: None,
+            surplus_policy: None,
+            lock_transfers: None,
+            staking_mode: None,
+            delegation: None,
+            stake_denom: "qtum".to_string(),
+            staking_token_address: "qtum".to_string()
+        }
+    }
+
+
+    // default_tiers are the long and short locks the contract used to hard-code
+    fn default_tiers() -> Vec<TierConfig> {
+        long_short_tiers(2, 3, 2, None)
+    }
+
+    // long_short_tiers are the default tiers with other rates, in percent
+    fn long_short_tiers(
+        long_tax: u64,
+        short_tax: u64,
+        penalty: u64,
+        long_weight: Option<Decimal>,
+    ) -> Vec<TierConfig> {
+        vec![
+            TierConfig {
+                weight: long_weight,
+                ..tier_config("long", 30 * 14400, Rate::Percent(long_tax), Rate::Percent(penalty))
+            },
+            tier_config("short", 15 * 14400, Rate::Percent(short_tax), Rate::Percent(penalty)),
+        ]
+    }
+
+    fn tier_config(id: &str, period: u64, tax: Rate, penalty: Rate) -> TierConfig {
+        TierConfig {
+            id: id.to_string(),
+            period,
+            tax,
+            penalty,
+            weight: None,
+        }
+    }
+
+    fn get_balance<U: Into<String>>(deps: Deps, addr: U) -> Uint128 {
+        query_balance(deps, addr.into()).unwrap().balance
+    }
+
+    // set_staking_balance makes the staking token report `balance` for this contract
+    fn set_staking_balance(
+        deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>,
+        balance: u128,
+    ) {
+        deps.querier.update_wasm(move |query| match query {
+            WasmQuery::Smart { .. } => SystemResult::Ok(ContractResult::Ok(
+                to_binary(&BalanceResponse {
+                    balance: Uint128::new(balance),
+                })
+                .unwrap(),
+            )),
+            _ => SystemResult::Err(SystemError::UnsupportedRequest {
+                kind: "wasm".to_string(),
+            }),
+        });
+    }
+
+    // tracked_staking_balance is what the contract expects to hold of the staking token
+    fn tracked_staking_balance(deps: &OwnedDeps<MockStorage, MockApi, MockQuerier>) -> u128 {
+        let supply = TOTAL_SUPPLY.load(&deps.storage).unwrap();
+        tracked_balance(&deps.storage, &supply).unwrap().u128()
+    }
+
+    // sync_staking_balance makes the staking token report exactly what the contract tracks
+    fn sync_staking_balance(deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>) {
+        let balance = tracked_staking_balance(deps);
+        set_staking_balance(deps, balance);
+    }
+
+    // deposit_staking_balance makes the staking token report `amount` sent in on top of what is tracked,
+    // as it does when a hook runs
+    fn deposit_staking_balance(deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>, amount: u128) {
+        let balance = tracked_staking_balance(deps) + amount;
+        set_staking_balance(deps, balance);
+    }
+
+    // Lcg is a tiny deterministic generator, so the property tests need no extra dependency
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn below(&mut self, max: u64) -> u64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (self.0 >> 33) % max
+        }
+    }
+
+    fn get_attribute<'a>(res: &'a Response, key: &str) -> &'a str {
+        &res.attributes.iter().find(|a| a.key == key).unwrap().value
+    }
+
+    fn lock_tokens(
+        deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>,
+        env: Env,
+        sender: &str,
+        amount: u128,
+        tier: &str,
+    ) -> Response {
+        deposit_staking_balance(deps, amount);
+        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: sender.to_string(),
+            amount: Uint128::new(amount),
+            msg: to_binary(&Cw20HookMsg::Lock {
+                tier: tier.to_string(),
+            })
+            .unwrap(),
+        });
+        execute(deps.as_mut(), env, mock_info("qtum", &[]), msg).unwrap()
+    }
+
+
+    #[test]
+    fn proper_instantiation() {
+        let mut deps = mock_dependencies_with_balance(&[]);
+        let creator = String::from("creator");
+
+        let msg = InstantiateMsg {
+            name: "xQtum".to_string(),
+            symbol: "xQtum".to_string(),
+            decimals: 6,
+            tiers: default_tiers(),
+            reward_token_address: None,
+            surplus_policy: None,
+            lock_transfers: None,
+            staking_mode: None,
+            delegation: None,
+            stake_denom: "qtum".to_string(),
+            staking_token_address: Addr::unchecked("qtum").to_string()
+        };
+        let info = mock_info(&creator, &[]);
+
+        let res =  instantiate(deps.as_mut(), mock_env(), info, msg.clone()).unwrap();
+        assert_eq!(0, res.messages.len());
+
+
+        let token = query_token_info(deps.as_ref()).unwrap();
+
+        assert_eq!(&token.name, &msg.name);
+        assert_eq!(&token.symbol, &msg.symbol);
+        assert_eq!(token.decimals, msg.decimals);
+        assert_eq!(token.total_supply, Uint128::zero());
+
+        // no balance
+        assert_eq!(get_balance(deps.as_ref(), &creator), Uint128::zero());
+
+        let staking_info = query_investment(deps.as_ref(), mock_env()).unwrap();
+        assert_eq!(&staking_info.owner, &creator);
+        assert_eq!(staking_info.staked_tokens, coin(0, "qtum"));
+        assert_eq!(staking_info.token_supply, Uint128::zero());
+
+        let tiers = query_tiers(deps.as_ref()).unwrap().tiers;
+        assert_eq!(
+            tiers,
+            vec![
+                TierResponse {
+                    id: "long".to_string(),
+                    period: Duration::Time(30 * 14400),
+                    tax: Decimal::percent(2),
+                    penalty: Decimal::percent(2),
+                    weight: Decimal::one(),
+                },
+                TierResponse {
+                    id: "short".to_string(),
+                    period: Duration::Time(15 * 14400),
+                    tax: Decimal::percent(3),
+                    penalty: Decimal::percent(2),
+                    weight: Decimal::one(),
+                },
+            ]
+        );
+
+    
+    }
+
+    #[test]
+    fn lock() {
+
+        let mut deps = mock_dependencies_with_balance(&[]);
+
+
+        let instantiate_msg = default_instantiate();
+        let info = mock_info("addr0000", &[]);
+        let _res = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+
+        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "addr0000".to_string(),
+            amount: Uint128::from(100u128),
+            msg: to_binary(&Cw20HookMsg::Lock { tier: "long".to_string() }).unwrap(),
+        });
+
+        let info = mock_info("qtum", &[]);
+        let env = mock_env();
+        deposit_staking_balance(&mut deps, 100);
+        let _res = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+
+
+        let supply_info = TOTAL_SUPPLY.load(deps.as_ref().storage).unwrap();
+
+        assert_eq!(supply_info.issued, Uint128::new(98) )
+
+    }
+
+    #[test]
+    fn lock_records_position() {
+        let mut deps = mock_dependencies_with_balance(&[]);
+        let info = mock_info("addr0000", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, default_instantiate()).unwrap();
+
+        let env = mock_env();
+        let res = lock_tokens(&mut deps, env.clone(), "addr0000", 100, "long");
+        assert_eq!(get_attribute(&res, "position_id"), "1");
+
+        let res = query_positions(deps.as_ref(), env.clone(), "addr0000".to_string(), None, None)
+            .unwrap();
+        assert_eq!(
+            res.positions,
+            vec![PositionResponse {
+                id: 1,
+                amount: Uint128::new(98),
+                tier: "long".to_string(),
+                start: env.block.time,
+                release: Expiration::AtTime(env.block.time.plus_seconds(30 * 14400)),
+                matured: false,
+                boost: Uint128::zero(),
+                pending_rewards: Uint128::zero(),
+            }]
+        );
+
+        // nothing recorded for anyone else
+        let res = query_positions(deps.as_ref(), env, "addr0001".to_string(), None, None).unwrap();
+        assert!(res.positions.is_empty());
+    }
+
+    #[test]
+    fn unlock_consumes_oldest_positions_first() {
+        let mut deps = mock_dependencies_with_balance(&[]);
+        let info = mock_info("addr0000", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, default_instantiate()).unwrap();
+
+        // short lock mints 97, long lock a day later mints 98
+        let mut env = mock_env();
+        lock_tokens(&mut deps, env.clone(), "addr0000", 100, "short");
+        env.block.time = env.block.time.plus_seconds(86400);
+        lock_tokens(&mut deps, env.clone(), "addr0000", 100, "long");
+        set_staking_balance(&mut deps, 200);
+
+        // the short position has matured, the long one has not
+        env.block.time = mock_env().block.time.plus_seconds(15 * 14400);
+        let msg = ExecuteMsg::UnLock {
+            amount: Uint128::new(150),
+            token_id: None,
+            position_ids: None,
+            strategy: None,
+        };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("addr0000", &[]), msg).unwrap();
+        assert_eq!(get_attribute(&res, "matured"), "97");
+        assert_eq!(get_attribute(&res, "unmatured"), "53");
+        // only the unmatured part pays the 2% penalty
+        assert_eq!(get_attribute(&res, "penalty"), "1");
+        assert_eq!(get_attribute(&res, "unlocked"), "149");
+        let tiers: Vec<_> = res
+            .attributes
+            .iter()
+            .filter(|a| a.key == "tier")
+            .map(|a| a.value.as_str())
+            .collect();
+        assert_eq!(tiers, vec!["short", "long"]);
+
+        let res = query_positions(deps.as_ref(), env, "addr0000".to_string(), None, None).unwrap();
+        assert_eq!(res.positions.len(), 1);
+        assert_eq!(res.positions[0].id, 2);
+        assert_eq!(res.positions[0].amount, Uint128::new(45));
+        assert!(!res.positions[0].matured);
+    }
+
+    #[test]
+    fn unlock_and_claim_staking_tokens() {
+        let mut deps = mock_dependencies_with_balance(&[]);
+        let info = mock_info("addr0000", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, default_instantiate()).unwrap();
+
+        lock_tokens(&mut deps, mock_env(), "addr0000", 1000, "long");
+        set_staking_balance(&mut deps, 1000);
+
+        // 980 minted, unlock 500 of them with a 2% penalty
+        let msg = ExecuteMsg::UnLock {
+            amount: Uint128::new(500),
+            token_id: None,
+            position_ids: None,
+            strategy: None,
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("addr0000", &[]), msg).unwrap();
+        assert!(res.messages.is_empty());
+        assert_eq!(get_attribute(&res, "early_exit"), "true");
+        assert_eq!(get_balance(deps.as_ref(), "addr0000"), Uint128::new(480));
+
+        let supply = TOTAL_SUPPLY.load(deps.as_ref().storage).unwrap();
+        assert_eq!(supply.locked, Uint128::new(480));
+        assert_eq!(supply.claims, Uint128::new(490));
+        assert_eq!(supply.issued, Uint128::new(480));
+        // 20 entry tax plus 10 penalty
+        assert_eq!(supply.fees, Uint128::new(30));
+
+        // the early exit paid the penalty, so the claim is released right away
+        let claims = CLAIMS
+            .query_claims(deps.as_ref(), &Addr::unchecked("addr0000"))
+            .unwrap()
+            .claims;
+        assert_eq!(claims.len(), 1);
+        assert_eq!(claims[0].amount, Uint128::new(490));
+        assert_eq!(claims[0].release_at, Expiration::AtTime(mock_env().block.time));
+
+        let env = mock_env();
+        let res = execute(deps.as_mut(), env.clone(), mock_info("addr0000", &[]), ExecuteMsg::Claim {})
+            .unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(WasmMsg::Execute {
+                contract_addr: "qtum".to_string(),
+                msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                    recipient: "addr0000".to_string(),
+                    amount: Uint128::new(490),
+                })
+                .unwrap(),
+                funds: vec![],
+            })]
+        );
+        let supply = TOTAL_SUPPLY.load(deps.as_ref().storage).unwrap();
+        assert_eq!(supply.claims, Uint128::zero());
+
+        // nothing left to claim
+        let err = execute(deps.as_mut(), env, mock_info("addr0000", &[]), ExecuteMsg::Claim {})
+            .unwrap_err();
+        assert_eq!(err, ContractError::NothingToClaim {});
+
+        // cannot unlock more than the remaining balance
+        set_staking_balance(&mut deps, 510);
+        let msg = ExecuteMsg::UnLock {
+            amount: Uint128::new(481),
+            token_id: None,
+            position_ids: None,
+            strategy: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("addr0000", &[]), msg).unwrap_err();
+    }
+
+    #[test]
+    fn claim_without_released_tokens_fails() {
+        let mut deps = mock_dependencies_with_balance(&[]);
+        let info = mock_info("creator", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, default_instantiate()).unwrap();
+        lock_tokens(&mut deps, mock_env(), "addr0000", 1000, "long");
+
+        // locked tokens are not a claim
+        let err = execute(deps.as_mut(), mock_env(), mock_info("addr0000", &[]), ExecuteMsg::Claim {})
+            .unwrap_err();
+        assert_eq!(err, ContractError::NothingToClaim {});
+        let err = execute(deps.as_mut(), mock_env(), mock_info("addr0001", &[]), ExecuteMsg::Claim {})
+            .unwrap_err();
+        assert_eq!(err, ContractError::NothingToClaim {});
+    }
+
+    #[test]
+    fn matured_unlock_pays_no_penalty() {
+        let mut deps = mock_dependencies_with_balance(&[]);
+        let info = mock_info("creator", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, default_instantiate()).unwrap();
+        lock_tokens(&mut deps, mock_env(), "addr0000", 1000, "long");
+        set_staking_balance(&mut deps, 1000);
+
+        let unlock = ExecuteMsg::UnLock {
+            amount: Uint128::new(490),
+            token_id: None,
+            position_ids: None,
+            strategy: None,
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("addr0000", &[]), unlock.clone())
+            .unwrap();
+        assert_eq!(get_attribute(&res, "early_exit"), "true");
+        assert_eq!(get_attribute(&res, "penalty"), "9");
+        assert_eq!(get_attribute(&res, "unlocked"), "481");
+
+        // the other half waits for the 30 days of the long tier
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(30 * 14400);
+        sync_staking_balance(&mut deps);
+        let res = execute(deps.as_mut(), env.clone(), mock_info("addr0000", &[]), unlock).unwrap();
+        assert_eq!(get_attribute(&res, "early_exit"), "false");
+        assert_eq!(get_attribute(&res, "penalty"), "0");
+        assert_eq!(get_attribute(&res, "unlocked"), "490");
+        let claims = CLAIMS
+            .query_claims(deps.as_ref(), &Addr::unchecked("addr0000"))
+            .unwrap()
+            .claims;
+        assert_eq!(
+            claims.iter().map(|claim| claim.amount).collect::<Vec<_>>(),
+            vec![Uint128::new(481), Uint128::new(490)]
+        );
+        assert_eq!(TOTAL_SUPPLY.load(&deps.storage).unwrap().fees, Uint128::new(20 + 9));
+    }
+
+    #[test]
+    fn owner_withdraws_fees() {
+        let mut deps = mock_dependencies_with_balance(&[]);
+        let info = mock_info("creator", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, default_instantiate()).unwrap();
+
+        // 20 tax on lock, 10 penalty on an early unlock
+        lock_tokens(&mut deps, mock_env(), "addr0000", 1000, "long");
+        set_staking_balance(&mut deps, 1000);
+        let msg = ExecuteMsg::UnLock {
+            amount: Uint128::new(500),
+            token_id: None,
+            position_ids: None,
+            strategy: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("addr0000", &[]), msg).unwrap();
+
+        assert_eq!(
+            query_fees(deps.as_ref(), mock_env()).unwrap(),
+            FeesResponse {
+                tax: Uint128::new(20),
+                penalty: Uint128::new(10),
+                available: Uint128::new(30),
+                unbonding: Uint128::zero(),
+            }
+        );
+
+        // only the owner can withdraw
+        let msg = ExecuteMsg::WithdrawFees {
+            recipient: "treasury".to_string(),
+            amount: Uint128::new(25),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("addr0000", &[]), msg.clone())
+            .unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        let res = execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(WasmMsg::Execute {
+                contract_addr: "qtum".to_string(),
+                msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                    recipient: "treasury".to_string(),
+                    amount: Uint128::new(25),
+                })
+                .unwrap(),
+                funds: vec![],
+            })]
+        );
+        assert_eq!(query_fees(deps.as_ref(), mock_env()).unwrap().available, Uint128::new(5));
+
+        // cannot take more than what is left
+        let msg = ExecuteMsg::WithdrawFees {
+            recipient: "treasury".to_string(),
+            amount: Uint128::new(6),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InsufficientFees {
+                available: Uint128::new(5)
+            }
+        );
+    }
+
+    #[test]
+    fn ownership_transfer() {
+        let mut deps = mock_dependencies_with_balance(&[]);
+        let info = mock_info("creator", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, default_instantiate()).unwrap();
+
+        let expiry = Expiration::AtHeight(mock_env().block.height + 10);
+        let propose = ExecuteMsg::ProposeNewOwner {
+            owner: "multisig".to_string(),
+            expiry: Some(expiry),
+        };
+
+        // only the owner can propose
+        let err = execute(deps.as_mut(), mock_env(), mock_info("anyone", &[]), propose.clone())
+            .unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), propose).unwrap();
+        assert_eq!(
+            query_ownership(deps.as_ref()).unwrap(),
+            OwnershipResponse {
+                owner: "creator".to_string(),
+                pending_owner: Some("multisig".to_string()),
+                pending_expiry: Some(expiry),
+            }
+        );
+
+        // only the proposed owner can accept, and only before expiry
+        let err = execute(deps.as_mut(), mock_env(), mock_info("anyone", &[]), ExecuteMsg::AcceptOwnership {})
+            .unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        let mut env = mock_env();
+        env.block.height += 10;
+        let err = execute(deps.as_mut(), env, mock_info("multisig", &[]), ExecuteMsg::AcceptOwnership {})
+            .unwrap_err();
+        assert_eq!(err, ContractError::OwnershipProposalExpired {});
+
+        execute(deps.as_mut(), mock_env(), mock_info("multisig", &[]), ExecuteMsg::AcceptOwnership {})
+            .unwrap();
+        assert_eq!(
+            query_ownership(deps.as_ref()).unwrap(),
+            OwnershipResponse {
+                owner: "multisig".to_string(),
+                pending_owner: None,
+                pending_expiry: None,
+            }
+        );
+        assert_eq!(query_investment(deps.as_ref(), mock_env()).unwrap().owner, "multisig");
+
+        // the old owner lost its rights
+        let propose = ExecuteMsg::ProposeNewOwner {
+            owner: "creator".to_string(),
+            expiry: None,
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), propose.clone())
+            .unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        // a proposal can be cancelled
+        execute(deps.as_mut(), mock_env(), mock_info("multisig", &[]), propose).unwrap();
+        execute(deps.as_mut(), mock_env(), mock_info("multisig", &[]), ExecuteMsg::CancelOwnershipProposal {})
+            .unwrap();
+        let err = execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), ExecuteMsg::AcceptOwnership {})
+            .unwrap_err();
+        assert_eq!(err, ContractError::NoPendingOwner {});
+    }
+
+    #[test]
+    fn set_tier_applies_to_new_positions() {
+        let mut deps = mock_dependencies_with_balance(&[]);
+        let info = mock_info("creator", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, default_instantiate()).unwrap();
+
+        lock_tokens(&mut deps, mock_env(), "addr0000", 1000, "long");
+
+        let msg = ExecuteMsg::SetTier {
+            tier: tier_config("long", 60 * 14400, Rate::Percent(5), Rate::Percent(10)),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("addr0000", &[]), msg.clone())
+            .unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        let res = execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        assert_eq!(get_attribute(&res, "tier"), "long");
+        assert_eq!(get_attribute(&res, "replaced"), "true");
+        assert_eq!(get_attribute(&res, "period"), Duration::Time(60 * 14400).to_string());
+        assert_eq!(get_attribute(&res, "tax"), "0.05");
+        assert_eq!(get_attribute(&res, "penalty"), "0.1");
+        assert_eq!(get_attribute(&res, "old_period"), Duration::Time(30 * 14400).to_string());
+        assert_eq!(get_attribute(&res, "old_tax"), "0.02");
+        assert_eq!(get_attribute(&res, "old_penalty"), "0.02");
+        assert_eq!(get_attribute(&res, "old_weight"), "1");
+
+        let tiers = query_tiers(deps.as_ref()).unwrap().tiers;
+        assert_eq!(tiers[0].tax, Decimal::percent(5));
+        assert_eq!(tiers[0].period, Duration::Time(60 * 14400));
+        assert_eq!(tiers[0].penalty, Decimal::percent(10));
+
+        // new positions use the new tier
+        lock_tokens(&mut deps, mock_env(), "addr0000", 1000, "long");
+        let positions =
+            query_positions(deps.as_ref(), mock_env(), "addr0000".to_string(), None, None)
+                .unwrap()
+                .positions;
+        assert_eq!(positions[0].amount, Uint128::new(980));
+        assert_eq!(
+            positions[0].release,
+            Expiration::AtTime(mock_env().block.time.plus_seconds(30 * 14400))
+        );
+        assert_eq!(positions[1].amount, Uint128::new(950));
+        assert_eq!(
+            positions[1].release,
+            Expiration::AtTime(mock_env().block.time.plus_seconds(60 * 14400))
+        );
+
+        // the old position keeps its 2% penalty
+        set_staking_balance(&mut deps, 2000);
+        let msg = ExecuteMsg::UnLock {
+            amount: Uint128::new(980),
+            token_id: None,
+            position_ids: None,
+            strategy: None,
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("addr0000", &[]), msg).unwrap();
+        assert_eq!(get_attribute(&res, "penalty"), "19");
+
+        // rates are capped at 100%
+        let msg = ExecuteMsg::SetTier {
+            tier: tier_config("short", 15 * 14400, Rate::Percent(101), Rate::Percent(2)),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InvalidRate {
+                name: "short_tax".to_string(),
+                rate: Decimal::percent(101)
+            }
+        );
+    }
+
+    #[test]
+    fn owner_adds_and_removes_tiers() {
+        let mut deps = mock_dependencies_with_balance(&[]);
+        let info = mock_info("creator", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, default_instantiate()).unwrap();
+
+        // a third tier, locked for 90 days with a boost
+        let msg = ExecuteMsg::SetTier {
+            tier: TierConfig {
+                weight: Some(Decimal::percent(200)),
+                ..tier_config("max", 90 * 14400, Rate::Percent(1), Rate::Percent(20))
+            },
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        assert_eq!(get_attribute(&res, "replaced"), "false");
+        assert!(!res.attributes.iter().any(|attr| attr.key == "old_tax"));
+        assert_eq!(query_tiers(deps.as_ref()).unwrap().tiers.len(), 3);
+
+        let res = lock_tokens(&mut deps, mock_env(), "addr0000", 1000, "max");
+        assert_eq!(get_attribute(&res, "tier"), "max");
+        assert_eq!(get_attribute(&res, "tax"), "10");
+        let position = positions().load(&deps.storage, 1).unwrap();
+        assert_eq!(position.penalty, Decimal::percent(20));
+        assert_eq!(position.boost, Uint128::new(990));
+        assert_eq!(
+            position.release,
+            Expiration::AtTime(mock_env().block.time.plus_seconds(90 * 14400))
+        );
+
+        let msg = ExecuteMsg::RemoveTier {
+            id: "max".to_string(),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("addr0000", &[]), msg.clone())
+            .unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg.clone()).unwrap();
+        let err = execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::TierNotFound { id: "max".to_string() });
+
+        // removed tiers can't be picked, open positions keep their terms
+        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "addr0000".to_string(),
+            amount: Uint128::new(1000),
+            msg: to_binary(&Cw20HookMsg::Lock { tier: "max".to_string() }).unwrap(),
+        });
+        let err = execute(deps.as_mut(), mock_env(), mock_info("qtum", &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::TierNotFound { id: "max".to_string() });
+        let position = positions().load(&deps.storage, 1).unwrap();
+        assert_eq!(position.tier, "max");
+        assert_eq!(position.penalty, Decimal::percent(20));
+
+        // moving off a removed tier compares with the terms the position recorded
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(90 * 14400);
+        let extend = |tier: &str| ExecuteMsg::ExtendLock {
+            position_id: 1,
+            tier: tier.to_string(),
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("addr0000", &[]), extend("long"))
+            .unwrap_err();
+        assert_eq!(err, ContractError::CannotShortenLock {});
+        let msg = ExecuteMsg::SetTier {
+            tier: tier_config("year", 120 * 14400, Rate::Percent(5), Rate::Percent(20)),
+        };
+        execute(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+        let res = execute(deps.as_mut(), env, mock_info("addr0000", &[]), extend("year")).unwrap();
+        assert_eq!(get_attribute(&res, "tax"), "39");
+    }
+
+    #[test]
+    fn tier_ids_are_unique_and_not_empty() {
+        let mut deps = mock_dependencies_with_balance(&[]);
+        let info = mock_info("creator", &[]);
+        let mut tiers = default_tiers();
+        tiers.push(tier_config("long", 90 * 14400, Rate::Percent(1), Rate::Percent(20)));
+        let msg = InstantiateMsg {
+            tiers,
+            ..default_instantiate()
+        };
+        let err = instantiate(deps.as_mut(), mock_env(), info.clone(), msg).unwrap_err();
+        assert_eq!(err, ContractError::DuplicateTier { id: "long".to_string() });
+
+        let mut tiers = default_tiers();
+        tiers.push(tier_config("", 90 * 14400, Rate::Percent(1), Rate::Percent(20)));
+        let msg = InstantiateMsg {
+            tiers,
+            ..default_instantiate()
+        };
+        let mut deps = mock_dependencies_with_balance(&[]);
+        let err = instantiate(deps.as_mut(), mock_env(), info.clone(), msg).unwrap_err();
+        assert_eq!(err, ContractError::EmptyTierId {});
+
+        let mut deps = mock_dependencies_with_balance(&[]);
+        instantiate(deps.as_mut(), mock_env(), info, default_instantiate()).unwrap();
+        let msg = ExecuteMsg::SetTier {
+            tier: tier_config("", 90 * 14400, Rate::Percent(1), Rate::Percent(20)),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::EmptyTierId {});
+    }
+
+    #[test]
+    fn migrate_from_v0_14() {
+        let mut deps = mock_dependencies_with_balance(&[]);
+
+        // state as 0.14 left it: penalties were part of locked and counted again as fees
+        set_contract_version(deps.as_mut().storage, CONTRACT_NAME, "0.14.2").unwrap();
+        deps.storage.set(
+            b"total_supply",
+            br#"{"issued":"100","locked":"110","fees":"10"}"#,
+        );
+        let token_info = TokenInfo {
+            name: "xQtum".to_string(),
+            symbol: "xQtum".to_string(),
+            decimals: 6,
+            total_supply: Uint128::new(100),
+            mint: Some(MinterData {
+                minter: mock_env().contract.address,
+                cap: None,
+            }),
+        };
+        TOKEN_INFO.save(deps.as_mut().storage, &token_info).unwrap();
+        BALANCES
+            .save(deps.as_mut().storage, &Addr::unchecked("addr0000"), &Uint128::new(100))
+            .unwrap();
+        // with the fixed long and short locks
+        let staking_token = to_binary(&deps.api.addr_canonicalize("qtum").unwrap()).unwrap();
+        let staking_info = format!(
+            r#"{{"owner":"creator","stake_denom":"qtum","staking_token_address":{},
+                "period":{{"long":{{"time":2592000}},"short":{{"time":432000}}}},
+                "tax":{{"long":"0.05","short":"0.01"}},"penalty":"0.1"}}"#,
+            String::from_utf8(staking_token.to_vec()).unwrap()
+        );
+        deps.storage.set(b"staking_info", staking_info.as_bytes());
+
+        let res = migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+        assert_eq!(get_attribute(&res, "from_version"), "0.14.2");
+        assert_eq!(get_contract_version(&deps.storage).unwrap().version, CONTRACT_VERSION);
+
+        let supply = TOTAL_SUPPLY.load(&deps.storage).unwrap();
+        assert_eq!(supply.issued, Uint128::new(100));
+        assert_eq!(supply.locked, Uint128::new(100));
+        assert_eq!(supply.claims, Uint128::zero());
+        assert_eq!(supply.fees, Uint128::new(10));
+        assert_eq!(query_fees(deps.as_ref(), mock_env()).unwrap().penalty, Uint128::new(10));
+
+        // existing holders need no position
+        let held = query_positions(deps.as_ref(), mock_env(), "addr0000".to_string(), None, None)
+            .unwrap()
+            .positions;
+        assert!(held.is_empty());
+
+        // the long and short locks become tiers
+        let tiers = query_tiers(deps.as_ref()).unwrap().tiers;
+        assert_eq!(
+            tiers,
+            vec![
+                TierResponse {
+                    id: "long".to_string(),
+                    period: Duration::Time(2592000),
+                    tax: Decimal::percent(5),
+                    penalty: Decimal::percent(10),
+                    weight: Decimal::one(),
+                },
+                TierResponse {
+                    id: "short".to_string(),
+                    period: Duration::Time(432000),
+                    tax: Decimal::percent(1),
+                    penalty: Decimal::percent(10),
+                    weight: Decimal::one(),
+                },
+            ]
+        );
+        let stake_info = STAKING_INFO.load(&deps.storage).unwrap();
+        assert_eq!(stake_info.owner, "creator");
+        assert_eq!(stake_info.surplus_policy, SurplusPolicy::Sweep {});
+
+        // migrating again to the same version is a no-op
+        migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+        assert_eq!(TOTAL_SUPPLY.load(&deps.storage).unwrap(), supply);
+
+        // the holder's tokens unlock as matured
+        set_staking_balance(&mut deps, 110);
+        let msg = ExecuteMsg::UnLock {
+            amount: Uint128::new(100),
+            token_id: None,
+            position_ids: None,
+            strategy: None,
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("addr0000", &[]), msg).unwrap();
+        assert_eq!(get_attribute(&res, "matured"), "100");
+        assert_eq!(get_attribute(&res, "penalty"), "0");
+    }
+
+    #[test]
+    fn migrate_rejects_downgrade_and_other_contracts() {
+        let mut deps = mock_dependencies_with_balance(&[]);
+        let info = mock_info("creator", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, default_instantiate()).unwrap();
+
+        set_contract_version(deps.as_mut().storage, CONTRACT_NAME, "99.0.0").unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::CannotMigrate {
+                stored: "99.0.0".to_string(),
+                current: CONTRACT_VERSION.to_string()
+            }
+        );
+
+        set_contract_version(deps.as_mut().storage, "crates.io:cw20-base", "0.1.0").unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::CannotMigrate {
+                stored: "crates.io:cw20-base".to_string(),
+                current: CONTRACT_NAME.to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn instantiate_rejects_invalid_config() {
+        let mut deps = mock_dependencies_with_balance(&[]);
+        let info = mock_info("creator", &[]);
+
+        let msg = InstantiateMsg {
+            tiers: vec![tier_config("long", 30 * 14400, Rate::Percent(150), Rate::Percent(2))],
+            ..default_instantiate()
+        };
+        let err = instantiate(deps.as_mut(), mock_env(), info.clone(), msg).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InvalidRate {
+                name: "long_tax".to_string(),
+                rate: Decimal::percent(150)
+            }
+        );
+
+        let msg = InstantiateMsg {
+            tiers: vec![tier_config("short", 15 * 14400, Rate::Percent(3), Rate::Percent(200))],
+            ..default_instantiate()
+        };
+        let err = instantiate(deps.as_mut(), mock_env(), info.clone(), msg).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InvalidRate {
+                name: "short_penalty".to_string(),
+                rate: Decimal::percent(200)
+            }
+        );
+
+        let msg = InstantiateMsg {
+            tiers: vec![TierConfig {
+                weight: Some(Decimal::percent(50)),
+                ..tier_config("long", 30 * 14400, Rate::Percent(2), Rate::Percent(2))
+            }],
+            ..default_instantiate()
+        };
+        let err = instantiate(deps.as_mut(), mock_env(), info.clone(), msg).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InvalidWeight {
+                name: "long_weight".to_string(),
+                weight: Decimal::percent(50)
+            }
+        );
+
+        let msg = InstantiateMsg {
+            tiers: vec![tier_config("flash", 0, Rate::Percent(2), Rate::Percent(2))],
+            ..default_instantiate()
+        };
+        let err = instantiate(deps.as_mut(), mock_env(), info.clone(), msg).unwrap_err();
+        assert_eq!(err, ContractError::InvalidPeriod { id: "flash".to_string() });
+
+        let msg = InstantiateMsg {
+            decimals: 19,
+            ..default_instantiate()
+        };
+        let err = instantiate(deps.as_mut(), mock_env(), info.clone(), msg).unwrap_err();
+        assert_eq!(err, ContractError::InvalidDecimals { decimals: 19 });
+
+        let msg = InstantiateMsg {
+            staking_token_address: "Qtum".to_string(),
+            ..default_instantiate()
+        };
+        instantiate(deps.as_mut(), mock_env(), info.clone(), msg).unwrap_err();
+
+        // set tier runs the same checks
+        instantiate(deps.as_mut(), mock_env(), info.clone(), default_instantiate()).unwrap();
+        let msg = ExecuteMsg::SetTier {
+            tier: tier_config("long", 0, Rate::Percent(2), Rate::Percent(2)),
+        };
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::InvalidPeriod { id: "long".to_string() });
+    }
+
+    #[test]
+    fn rates_accept_percent_and_decimal() {
+        let msg: InstantiateMsg = from_binary(&Binary::from(
+            br#"{
+                "name": "xQtum", "symbol": "xQtum", "decimals": 6,
+                "stake_denom": "qtum", "staking_token_address": "qtum",
+                "tiers": [
+                    {"id": "long", "period": 432000, "tax": 2, "penalty": "0.0225"},
+                    {"id": "short", "period": 216000, "tax": "0.005", "penalty": 2}
+                ]
+            }"#
+            .as_slice(),
+        ))
+        .unwrap();
+        assert_eq!(msg.tiers[0].tax, Rate::Percent(2));
+        assert_eq!(msg.tiers[1].tax, Rate::Decimal(Decimal::permille(5)));
+
+        let mut deps = mock_dependencies_with_balance(&[]);
+        instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        let tiers = query_tiers(deps.as_ref()).unwrap().tiers;
+        assert_eq!(tiers[0].tax, Decimal::percent(2));
+        assert_eq!(tiers[0].penalty, Decimal::from_ratio(225u128, 10000u128));
+        assert_eq!(tiers[1].tax, Decimal::permille(5));
+
+        // 0.5% of 1000
+        let res = lock_tokens(&mut deps, mock_env(), "addr0000", 1000, "short");
+        assert_eq!(get_attribute(&res, "tax"), "5");
+
+        let msg = ExecuteMsg::SetTier {
+            tier: tier_config(
+                "long",
+                432000,
+                Rate::Decimal(Decimal::from_ratio(1u128, 400u128)),
+                Rate::Percent(2),
+            ),
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        assert_eq!(get_attribute(&res, "tax"), "0.0025");
+    }
+
+    #[test]
+    fn lock_mints_at_exchange_rate() {
+        let mut deps = mock_dependencies_with_balance(&[]);
+        let info = mock_info("creator", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, default_instantiate()).unwrap();
+
+        // empty contract mints at the fallback ratio
+        lock_tokens(&mut deps, mock_env(), "addr0000", 1000, "long");
+        assert_eq!(get_balance(deps.as_ref(), "addr0000"), Uint128::new(980));
+
+        // the pool grows by half, so one share is now worth 1.5 tokens
+        TOTAL_SUPPLY
+            .update(deps.as_mut().storage, |mut supply| -> StdResult<_> {
+                supply.locked += Uint128::new(490);
+                Ok(supply)
+            })
+            .unwrap();
+        assert_eq!(
+            query_investment(deps.as_ref(), mock_env()).unwrap().nominal_value,
+            Decimal::percent(150)
+        );
+
+        // 980 after tax buys 980 / 1.5 shares, rounded down
+        let res = lock_tokens(&mut deps, mock_env(), "addr0001", 1000, "long");
+        assert_eq!(get_attribute(&res, "minted"), "653");
+        assert_eq!(get_balance(deps.as_ref(), "addr0001"), Uint128::new(653));
+        let supply = TOTAL_SUPPLY.load(&deps.storage).unwrap();
+        assert_eq!(supply.locked, Uint128::new(2450));
+        assert_eq!(supply.issued, Uint128::new(1633));
+
+        // burning the shares gives back their value at the same rate, less the early-exit penalty
+        set_staking_balance(&mut deps, 2450 + 40);
+        let msg = ExecuteMsg::UnLock {
+            amount: Uint128::new(653),
+            token_id: None,
+            position_ids: None,
+            strategy: None,
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("addr0001", &[]), msg).unwrap();
+        assert_eq!(get_attribute(&res, "penalty"), "19");
+        assert_eq!(get_attribute(&res, "unlocked"), "960");
+        let supply = TOTAL_SUPPLY.load(&deps.storage).unwrap();
+        assert_eq!(supply.locked, Uint128::new(2450 - 979));
+        assert_eq!(supply.issued, Uint128::new(980));
+    }
+
+    #[test]
+    fn lock_unlock_sequences_never_drain_the_pool() {
+        let users = ["addr0000", "addr0001", "addr0002"];
+
+        for seed in 0..20 {
+            let mut rng = Lcg(seed);
+            let mut deps = mock_dependencies_with_balance(&[]);
+            let info = mock_info("creator", &[]);
+            instantiate(deps.as_mut(), mock_env(), info, default_instantiate()).unwrap();
+
+            let mut env = mock_env();
+            // everything that ever came in, and what was paid back to users
+            let mut deposited = Uint128::zero();
+            let mut paid = Uint128::zero();
+
+            for _ in 0..100 {
+                env.block.time = env.block.time.plus_seconds(rng.below(3 * 86400));
+                let user = users[rng.below(3) as usize];
+                let tier = if rng.below(2) == 0 { "long" } else { "short" };
+
+                match rng.below(5) {
+                    0 | 1 => {
+                        let amount = 1 + rng.below(100_000) as u128;
+                        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+                            sender: user.to_string(),
+                            amount: Uint128::new(amount),
+                            msg: to_binary(&Cw20HookMsg::Lock {
+                                tier: tier.to_string(),
+                            })
+                            .unwrap(),
+                        });
+                        deposit_staking_balance(&mut deps, amount);
+                        match execute(deps.as_mut(), env.clone(), mock_info("qtum", &[]), msg) {
+                            Ok(_) => deposited += Uint128::new(amount),
+                            // too small to buy a single share
+                            Err(err) => assert!(matches!(
+                                err,
+                                ContractError::InvalidZeroAmount {}
+                            )),
+                        }
+                    }
+                    2 => {
+                        let balance = get_balance(deps.as_ref(), user);
+                        if balance.is_zero() {
+                            continue;
+                        }
+                        let amount = 1 + rng.below(balance.u128() as u64) as u128;
+                        sync_staking_balance(&mut deps);
+                        let msg = ExecuteMsg::UnLock {
+                            amount: Uint128::new(amount),
+                            token_id: None,
+                            position_ids: None,
+                            strategy: None,
+                        };
+                        let res = execute(deps.as_mut(), env.clone(), mock_info(user, &[]), msg)
+                            .unwrap();
+                        paid += Uint128::new(get_attribute(&res, "unlocked").parse().unwrap());
+                    }
+                    3 => {
+                        // rewards raise the value of every share
+                        let supply = TOTAL_SUPPLY.load(&deps.storage).unwrap();
+                        if supply.issued.is_zero() {
+                            continue;
+                        }
+                        let reward = Uint128::new(rng.below(supply.locked.u128() as u64 / 10 + 1) as u128);
+                        TOTAL_SUPPLY
+                            .update(deps.as_mut().storage, |mut supply| -> StdResult<_> {
+                                supply.locked += reward;
+                                Ok(supply)
+                            })
+                            .unwrap();
+                        deposited += reward;
+                    }
+                    _ => {
+                        // a round trip never returns more than was put in
+                        let amount = 1 + rng.below(100_000) as u128;
+                        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+                            sender: "addr0009".to_string(),
+                            amount: Uint128::new(amount),
+                            msg: to_binary(&Cw20HookMsg::Lock {
+                                tier: tier.to_string(),
+                            })
+                            .unwrap(),
+                        });
+                        deposit_staking_balance(&mut deps, amount);
+                        if execute(deps.as_mut(), env.clone(), mock_info("qtum", &[]), msg).is_err() {
+                            continue;
+                        }
+                        sync_staking_balance(&mut deps);
+                        let msg = ExecuteMsg::UnLock {
+                            amount: get_balance(deps.as_ref(), "addr0009"),
+                            token_id: None,
+                            position_ids: None,
+                            strategy: None,
+                        };
+                        let res = execute(deps.as_mut(), env.clone(), mock_info("addr0009", &[]), msg)
+                            .unwrap();
+                        let unlocked: u128 = get_attribute(&res, "unlocked").parse().unwrap();
+                        assert!(unlocked <= amount, "seed {}: {} out for {} in", seed, unlocked, amount);
+                        deposited += Uint128::new(amount);
+                        paid += Uint128::new(unlocked);
+                    }
+                }
+
+                // no tokens are created, and all shares together are never worth more than the pool
+                let supply = TOTAL_SUPPLY.load(&deps.storage).unwrap();
+                assert_eq!(supply.locked + supply.claims + supply.fees, deposited);
+                assert_eq!(supply.claims, paid);
+                let held: Uint128 = users
+                    .iter()
+                    .map(|user| value_of(&supply, get_balance(deps.as_ref(), *user)))
+                    .sum();
+                assert!(held <= supply.locked, "seed {}", seed);
+            }
+
+            // once everyone is out, at most dust is left behind in the pool
+            for user in users {
+                let balance = get_balance(deps.as_ref(), user);
+                if balance.is_zero() {
+                    continue;
+                }
+                sync_staking_balance(&mut deps);
+                let msg = ExecuteMsg::UnLock {
+                    amount: balance,
+                    token_id: None,
+                    position_ids: None,
+                    strategy: None,
+                };
+                let res = execute(deps.as_mut(), env.clone(), mock_info(user, &[]), msg).unwrap();
+                paid += Uint128::new(get_attribute(&res, "unlocked").parse().unwrap());
+            }
+            let supply = TOTAL_SUPPLY.load(&deps.storage).unwrap();
+            assert_eq!(supply.issued, Uint128::zero());
+            assert_eq!(paid + supply.fees + supply.locked, deposited);
+            assert!(paid + supply.fees <= deposited);
+        }
+    }
+
+    #[test]
+    fn distribute_rewards_raises_nominal_value() {
+        let mut deps = mock_dependencies_with_balance(&[]);
+        let info = mock_info("creator", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, default_instantiate()).unwrap();
+
+        let distribute = |amount: u128| {
+            ExecuteMsg::Receive(Cw20ReceiveMsg {
+                sender: "rewarder".to_string(),
+                amount: Uint128::new(amount),
+                msg: to_binary(&Cw20HookMsg::DistributeRewards {}).unwrap(),
+            })
+        };
+
+        // nothing to distribute to yet
+        let err = execute(deps.as_mut(), mock_env(), mock_info("qtum", &[]), distribute(100))
+            .unwrap_err();
+        assert_eq!(err, ContractError::NoShares {});
+
+        lock_tokens(&mut deps, mock_env(), "addr0000", 1000, "long");
+
+        // only the staking token counts as rewards
+        let err = execute(deps.as_mut(), mock_env(), mock_info("other", &[]), distribute(490))
+            .unwrap_err();
+        assert_eq!(err, ContractError::InvalidStakingToken {});
+
+        let res = execute(deps.as_mut(), mock_env(), mock_info("qtum", &[]), distribute(490))
+            .unwrap();
+        assert_eq!(get_attribute(&res, "nominal_value"), "1.5");
+        let investment = query_investment(deps.as_ref(), mock_env()).unwrap();
+        assert_eq!(investment.token_supply, Uint128::new(980));
+        assert_eq!(investment.staked_tokens, coin(1470, "qtum"));
+
+        // the holder gets the rewards when unlocking a matured position
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(30 * 14400);
+        sync_staking_balance(&mut deps);
+        let msg = ExecuteMsg::UnLock {
+            amount: Uint128::new(980),
+            token_id: None,
+            position_ids: None,
+            strategy: None,
+        };
+        let res = execute(deps.as_mut(), env, mock_info("addr0000", &[]), msg).unwrap();
+        assert_eq!(get_attribute(&res, "unlocked"), "1469");
+    }
+
+    #[test]
+    fn inflated_exchange_rate_costs_the_donor() {
+        let mut deps = mock_dependencies_with_balance(&[]);
+        let info = mock_info("creator", &[]);
+        let msg = InstantiateMsg {
+            tiers: long_short_tiers(0, 0, 2, None),
+            ..default_instantiate()
+        };
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        // a single share made worth about 5000 tokens by a donation,
+        // the virtual share takes the other half
+        lock_tokens(&mut deps, mock_env(), "attacker", 1, "long");
+        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "attacker".to_string(),
+            amount: Uint128::new(10000),
+            msg: to_binary(&Cw20HookMsg::DistributeRewards {}).unwrap(),
+        });
+        execute(deps.as_mut(), mock_env(), mock_info("qtum", &[]), msg).unwrap();
+
+        // the victim rounds away part of the lock, but the attacker loses more
+        let res = lock_tokens(&mut deps, mock_env(), "victim", 19000, "long");
+        assert_eq!(get_attribute(&res, "minted"), "3");
+        let supply = TOTAL_SUPPLY.load(&deps.storage).unwrap();
+        assert_eq!(value_of(&supply, Uint128::new(3)), Uint128::new(17401));
+        assert_eq!(value_of(&supply, Uint128::new(1)), Uint128::new(5800));
+        assert!(value_of(&supply, supply.issued) <= supply.locked);
+    }
+
+    #[test]
+    fn reward_schedule_streams_into_pool() {
+        let mut deps = mock_dependencies_with_balance(&[]);
+        let info = mock_info("creator", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, default_instantiate()).unwrap();
+        lock_tokens(&mut deps, mock_env(), "addr0000", 1000, "long");
+
+        let now = mock_env().block.time;
+        let fund = |sender: &str, start: Timestamp, end: Timestamp| {
+            ExecuteMsg::Receive(Cw20ReceiveMsg {
+                sender: sender.to_string(),
+                amount: Uint128::new(1000),
+                msg: to_binary(&Cw20HookMsg::FundRewards { start, end }).unwrap(),
+            })
+        };
+
+        let msg = fund("addr0000", now, now.plus_seconds(1000));
+        let err = execute(deps.as_mut(), mock_env(), mock_info("qtum", &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        let msg = fund("creator", now, now);
+        let err = execute(deps.as_mut(), mock_env(), mock_info("qtum", &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::InvalidRewardSchedule {});
+
+        // 1000 tokens over 1000 seconds
+        let msg = fund("creator", now, now.plus_seconds(1000));
+        execute(deps.as_mut(), mock_env(), mock_info("qtum", &[]), msg).unwrap();
+
+        let mut env = mock_env();
+        env.block.time = now.plus_seconds(500);
+        let investment = query_investment(deps.as_ref(), env.clone()).unwrap();
+        assert_eq!(investment.staked_tokens, coin(1480, "qtum"));
+        assert_eq!(investment.reward_rate, Decimal::one());
+        assert_eq!(investment.apr, Decimal::from_ratio(SECONDS_PER_YEAR, 1480u64));
+        // the query does not write anything
+        assert_eq!(TOTAL_SUPPLY.load(&deps.storage).unwrap().locked, Uint128::new(980));
+
+        // a lock accrues the rewards first, so the newcomer pays the higher price
+        let res = lock_tokens(&mut deps, env.clone(), "addr0001", 1480, "short");
+        let supply = TOTAL_SUPPLY.load(&deps.storage).unwrap();
+        assert_eq!(supply.locked, Uint128::new(1480 + 1436));
+        assert_eq!(get_attribute(&res, "minted"), "951");
+
+        // after the window everything has been streamed in
+        env.block.time = now.plus_seconds(30 * 14400);
+        let investment = query_investment(deps.as_ref(), env.clone()).unwrap();
+        assert_eq!(investment.staked_tokens, coin(980 + 1436 + 1000, "qtum"));
+        assert_eq!(investment.reward_rate, Decimal::zero());
+
+        sync_staking_balance(&mut deps);
+        let msg = ExecuteMsg::UnLock {
+            amount: Uint128::new(980),
+            token_id: None,
+            position_ids: None,
+            strategy: None,
+        };
+        let res = execute(deps.as_mut(), env, mock_info("addr0000", &[]), msg).unwrap();
+        // 980 shares of 1931 own a little over half of the 3416 pool
+        assert_eq!(get_attribute(&res, "unlocked"), "1733");
+    }
+
+    #[test]
+    fn reward_schedule_waits_for_first_locker() {
+        let mut deps = mock_dependencies_with_balance(&[]);
+        let info = mock_info("creator", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, default_instantiate()).unwrap();
+
+        let now = mock_env().block.time;
+        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "creator".to_string(),
+            amount: Uint128::new(1000),
+            msg: to_binary(&Cw20HookMsg::FundRewards {
+                start: now,
+                end: now.plus_seconds(1000),
+            })
+            .unwrap(),
+        });
+        execute(deps.as_mut(), mock_env(), mock_info("qtum", &[]), msg).unwrap();
+
+        // nobody was there for the first half, so the first locker gets no backlog
+        let mut env = mock_env();
+        env.block.time = now.plus_seconds(500);
+        let res = lock_tokens(&mut deps, env.clone(), "addr0000", 1000, "long");
+        assert_eq!(get_attribute(&res, "minted"), "980");
+        let schedule = REWARD_SCHEDULE.load(&deps.storage).unwrap();
+        assert_eq!(schedule.start, env.block.time);
+        assert_eq!(schedule.total, Uint128::new(1000));
+
+        // and the full amount is streamed over what is left of the window
+        env.block.time = now.plus_seconds(750);
+        let investment = query_investment(deps.as_ref(), env).unwrap();
+        assert_eq!(investment.staked_tokens, coin(980 + 500, "qtum"));
+        assert_eq!(investment.reward_rate, Decimal::percent(200));
+    }
+
+    #[test]
+    fn reward_schedule_restarts_after_an_empty_window() {
+        let mut deps = mock_dependencies_with_balance(&[]);
+        let info = mock_info("creator", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, default_instantiate()).unwrap();
+
+        let now = mock_env().block.time;
+        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "creator".to_string(),
+            amount: Uint128::new(3000),
+            msg: to_binary(&Cw20HookMsg::FundRewards {
+                start: now,
+                end: now.plus_seconds(1000),
+            })
+            .unwrap(),
+        });
+        execute(deps.as_mut(), mock_env(), mock_info("qtum", &[]), msg).unwrap();
+
+        // nobody locked before the window ended, a late locker doesn't take all of it
+        let mut env = mock_env();
+        env.block.time = now.plus_seconds(2000);
+        let res = lock_tokens(&mut deps, env.clone(), "addr0000", 100, "long");
+        assert_eq!(get_attribute(&res, "minted"), "98");
+        let investment = query_investment(deps.as_ref(), env.clone()).unwrap();
+        assert_eq!(investment.staked_tokens, coin(98, "qtum"));
+        let schedule = REWARD_SCHEDULE.load(&deps.storage).unwrap();
+        assert_eq!(schedule.start, env.block.time);
+        assert_eq!(schedule.end, env.block.time.plus_seconds(1000));
+        assert_eq!(schedule.total, Uint128::new(3000));
+
+        // the rewards stream again over a window as long as the first one
+        env.block.time = env.block.time.plus_seconds(500);
+        let investment = query_investment(deps.as_ref(), env).unwrap();
+        assert_eq!(investment.staked_tokens, coin(98 + 1500, "qtum"));
+    }
 
-    use cosmwasm_std::testing::{
-         mock_dependencies_with_balance, mock_env, mock_info 
-    };
+    #[test]
+    fn long_locks_earn_boosted_rewards() {
+        let mut deps = mock_dependencies_with_balance(&[]);
+        let info = mock_info("creator", &[]);
+        let msg = InstantiateMsg {
+            tiers: long_short_tiers(0, 0, 2, Some(Decimal::percent(150))),
+            ..default_instantiate()
+        };
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        let tiers = query_tiers(deps.as_ref()).unwrap().tiers;
+        assert_eq!(tiers[0].weight, Decimal::percent(150));
+        assert_eq!(tiers[1].weight, Decimal::one());
 
- 
- 
-    fn default_instantiate() -> InstantiateMsg {
-        InstantiateMsg {
-            name: "xQtum".to_string(),
-            symbol: "xQtum".to_string(),
-            decimals: 6,
-            long_period:  30 * 14400,
-            short_period: 15 * 14400,
-            long_tax: 2,
-            short_tax: 3,
-            penalty: 2,
-            stake_denom: "qtum".to_string(),
-            staking_token_address: "qtum".to_string()
-        }
+        // 1000 long shares weigh 1500, 1500 short shares weigh 1500
+        lock_tokens(&mut deps, mock_env(), "addr0000", 1000, "long");
+        lock_tokens(&mut deps, mock_env(), "addr0001", 1500, "short");
+
+        let now = mock_env().block.time;
+        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "creator".to_string(),
+            amount: Uint128::new(3000),
+            msg: to_binary(&Cw20HookMsg::FundRewards {
+                start: now,
+                end: now.plus_seconds(1000),
+            })
+            .unwrap(),
+        });
+        execute(deps.as_mut(), mock_env(), mock_info("qtum", &[]), msg).unwrap();
+
+        // halfway, 1250 went to every share alike and 250 to the long boost
+        let mut env = mock_env();
+        env.block.time = now.plus_seconds(500);
+        let investment = query_investment(deps.as_ref(), env.clone()).unwrap();
+        assert_eq!(investment.staked_tokens, coin(2500 + 1250, "qtum"));
+        let positions = query_positions(deps.as_ref(), env.clone(), "addr0000".to_string(), None, None)
+            .unwrap()
+            .positions;
+        assert_eq!(positions[0].boost, Uint128::new(500));
+        assert_eq!(positions[0].pending_rewards, Uint128::new(250));
+
+        // once both have matured, the long lock earned 1.5 times as much per token
+        env.block.time = now.plus_seconds(30 * 14400);
+        sync_staking_balance(&mut deps);
+        let msg = ExecuteMsg::UnLock {
+            amount: Uint128::new(1000),
+            token_id: None,
+            position_ids: None,
+            strategy: None,
+        };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("addr0000", &[]), msg).unwrap();
+        assert_eq!(get_attribute(&res, "boost_rewards"), "500");
+        assert_eq!(get_attribute(&res, "unlocked"), "2499");
+
+        sync_staking_balance(&mut deps);
+        let msg = ExecuteMsg::UnLock {
+            amount: Uint128::new(1500),
+            token_id: None,
+            position_ids: None,
+            strategy: None,
+        };
+        let res = execute(deps.as_mut(), env, mock_info("addr0001", &[]), msg).unwrap();
+        assert_eq!(get_attribute(&res, "boost_rewards"), "0");
+        assert_eq!(get_attribute(&res, "unlocked"), "3000");
+
+        let boost = BOOST.load(&deps.storage).unwrap();
+        assert_eq!(boost.weight, Uint128::zero());
+        assert_eq!(boost.pending, Uint128::zero());
     }
 
+    #[test]
+    fn partial_unlock_pays_its_share_of_boost_rewards() {
+        let mut deps = mock_dependencies_with_balance(&[]);
+        let info = mock_info("creator", &[]);
+        let msg = InstantiateMsg {
+            tiers: long_short_tiers(0, 0, 0, Some(Decimal::percent(150))),
+            ..default_instantiate()
+        };
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        lock_tokens(&mut deps, mock_env(), "addr0000", 1000, "long");
+        lock_tokens(&mut deps, mock_env(), "addr0001", 1500, "short");
 
-    fn get_balance<U: Into<String>>(deps: Deps, addr: U) -> Uint128 {
-        query_balance(deps, addr.into()).unwrap().balance
+        let now = mock_env().block.time;
+        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "creator".to_string(),
+            amount: Uint128::new(3000),
+            msg: to_binary(&Cw20HookMsg::FundRewards {
+                start: now,
+                end: now.plus_seconds(1000),
+            })
+            .unwrap(),
+        });
+        execute(deps.as_mut(), mock_env(), mock_info("qtum", &[]), msg).unwrap();
+
+        // halfway the position earned 250, unlocking a tenth of it pays a tenth of that
+        let mut env = mock_env();
+        env.block.time = now.plus_seconds(500);
+        sync_staking_balance(&mut deps);
+        let msg = ExecuteMsg::UnLock {
+            amount: Uint128::new(100),
+            token_id: None,
+            position_ids: None,
+            strategy: None,
+        };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("addr0000", &[]), msg).unwrap();
+        assert_eq!(get_attribute(&res, "boost_rewards"), "25");
+
+        // the rest stays with the position, which keeps the boost of its 900 shares
+        let positions = query_positions(deps.as_ref(), env.clone(), "addr0000".to_string(), None, None)
+            .unwrap()
+            .positions;
+        assert_eq!(positions[0].boost, Uint128::new(450));
+        assert_eq!(positions[0].pending_rewards, Uint128::new(225));
+        let boost = BOOST.load(&deps.storage).unwrap();
+        assert_eq!(boost.weight, Uint128::new(450));
+        assert_eq!(boost.pending, Uint128::new(225));
+
+        // unlocking a single share can't take the rewards of the whole position
+        sync_staking_balance(&mut deps);
+        let msg = ExecuteMsg::UnLock {
+            amount: Uint128::new(1),
+            token_id: None,
+            position_ids: None,
+            strategy: None,
+        };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("addr0000", &[]), msg).unwrap();
+        assert_eq!(get_attribute(&res, "boost_rewards"), "0");
+        let positions = query_positions(deps.as_ref(), env, "addr0000".to_string(), None, None)
+            .unwrap()
+            .positions;
+        assert_eq!(positions[0].pending_rewards, Uint128::new(225));
     }
 
+    #[test]
+    fn rewards_follow_balances_across_transfers() {
+        let mut deps = mock_dependencies_with_balance(&[]);
+        let info = mock_info("creator", &[]);
+        let msg = InstantiateMsg {
+            tiers: long_short_tiers(0, 0, 2, None),
+            reward_token_address: Some("reward".to_string()),
+            ..default_instantiate()
+        };
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        lock_tokens(&mut deps, mock_env(), "addr0000", 1000, "short");
+        lock_tokens(&mut deps, mock_env(), "addr0001", 3000, "short");
+
+        let add_rewards = |amount: u128| {
+            ExecuteMsg::Receive(Cw20ReceiveMsg {
+                sender: "creator".to_string(),
+                amount: Uint128::new(amount),
+                msg: to_binary(&Cw20HookMsg::AddRewards {}).unwrap(),
+            })
+        };
+        // only the reward token can add rewards
+        let err = execute(deps.as_mut(), mock_env(), mock_info("qtum", &[]), add_rewards(400))
+            .unwrap_err();
+        assert_eq!(err, ContractError::InvalidRewardToken {});
+        execute(deps.as_mut(), mock_env(), mock_info("reward", &[]), add_rewards(400)).unwrap();
+
+        // rewards already earned stay with the sender, later ones follow the new balances
+        let msg = ExecuteMsg::Transfer {
+            recipient: "addr0000".to_string(),
+            amount: Uint128::new(1000),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("addr0001", &[]), msg).unwrap();
+        execute(deps.as_mut(), mock_env(), mock_info("reward", &[]), add_rewards(400)).unwrap();
+
+        let pending = query_pending_rewards(deps.as_ref(), "addr0000".to_string()).unwrap();
+        assert_eq!(
+            pending,
+            PendingRewardsResponse {
+                pending: Uint128::new(300),
+                reward_token: Some("reward".to_string()),
+            }
+        );
+        let pending = query_pending_rewards(deps.as_ref(), "addr0001".to_string()).unwrap();
+        assert_eq!(pending.pending, Uint128::new(500));
+
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("addr0000", &[]),
+            ExecuteMsg::ClaimRewards {},
+        )
+        .unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "reward".to_string(),
+                msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                    recipient: "addr0000".to_string(),
+                    amount: Uint128::new(300),
+                })
+                .unwrap(),
+                funds: vec![],
+            })
+        );
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("addr0000", &[]),
+            ExecuteMsg::ClaimRewards {},
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::NothingToClaim {});
+        assert_eq!(REWARD_POOL.load(&deps.storage).unwrap().pending, Uint128::new(500));
+
+        // the reward token is fixed once set
+        let msg = ExecuteMsg::UpdateConfig {
+            reward_token_address: Some("other".to_string()),
+            surplus_policy: None,
+            lock_transfers: None,
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::RewardTokenAlreadySet {});
+    }
 
     #[test]
-    fn proper_instantiation() {
+    fn stray_transfers_are_swept_or_donated() {
         let mut deps = mock_dependencies_with_balance(&[]);
-        let creator = String::from("creator");
+        let info = mock_info("creator", &[]);
+        let msg = InstantiateMsg {
+            tiers: long_short_tiers(0, 0, 2, None),
+            ..default_instantiate()
+        };
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        lock_tokens(&mut deps, mock_env(), "addr0000", 1000, "short");
+
+        // a plain transfer to the contract no longer blocks unlocks, the surplus is left alone
+        let tracked = tracked_staking_balance(&deps);
+        set_staking_balance(&mut deps, tracked + 500);
+        let msg = ExecuteMsg::UnLock {
+            amount: Uint128::new(100),
+            token_id: None,
+            position_ids: None,
+            strategy: None,
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("addr0000", &[]), msg).unwrap();
+        assert!(res.attributes.iter().all(|attr| attr.key != "donated"));
+        assert_eq!(TOTAL_SUPPLY.load(&deps.storage).unwrap().locked, Uint128::new(900));
+
+        // under the donate policy the next lock hands it to the current holders first
+        let msg = ExecuteMsg::UpdateConfig {
+            reward_token_address: None,
+            surplus_policy: Some(SurplusPolicy::Donate {}),
+            lock_transfers: None,
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        assert_eq!(get_attribute(&res, "new_surplus_policy"), "donate");
+
+        let tracked = tracked_staking_balance(&deps);
+        set_staking_balance(&mut deps, tracked + 500 + 1000);
+        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "addr0001".to_string(),
+            amount: Uint128::new(1000),
+            msg: to_binary(&Cw20HookMsg::Lock {
+                tier: "short".to_string(),
+            })
+            .unwrap(),
+        });
+        let res = execute(deps.as_mut(), mock_env(), mock_info("qtum", &[]), msg).unwrap();
+        assert_eq!(get_attribute(&res, "donated"), "500");
+        // 900 shares back 1400 tokens when the new ones are priced
+        assert_eq!(get_attribute(&res, "minted"), "643");
+        assert_eq!(tracked_staking_balance(&deps), tracked + 1500);
+
+        // missing tokens are still an error
+        let tracked = tracked_staking_balance(&deps);
+        set_staking_balance(&mut deps, tracked - 1);
+        let msg = ExecuteMsg::UnLock {
+            amount: Uint128::new(100),
+            token_id: None,
+            position_ids: None,
+            strategy: None,
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("addr0000", &[]), msg).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::LockedMismatch {
+                stored: Uint128::new(tracked),
+                queried: Uint128::new(tracked - 1),
+            }
+        );
+    }
+
+    #[test]
+    fn owner_sweeps_stray_tokens() {
+        let mut deps = mock_dependencies_with_balance(&[coin(50, "uatom")]);
+        let info = mock_info("creator", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, default_instantiate()).unwrap();
+        lock_tokens(&mut deps, mock_env(), "addr0000", 1000, "long");
+
+        let sweep = |token: SweepToken| ExecuteMsg::Sweep {
+            token,
+            recipient: "treasury".to_string(),
+        };
+        let staking_token = || SweepToken::Cw20 {
+            address: "qtum".to_string(),
+        };
+
+        // locked tokens and fees are never swept
+        sync_staking_balance(&mut deps);
+        let err = execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), sweep(staking_token()))
+            .unwrap_err();
+        assert_eq!(err, ContractError::NothingToSweep {});
+
+        let tracked = tracked_staking_balance(&deps);
+        set_staking_balance(&mut deps, tracked + 300);
+        let err = execute(deps.as_mut(), mock_env(), mock_info("addr0000", &[]), sweep(staking_token()))
+            .unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        let res = execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), sweep(staking_token()))
+            .unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(WasmMsg::Execute {
+                contract_addr: "qtum".to_string(),
+                msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                    recipient: "treasury".to_string(),
+                    amount: Uint128::new(300),
+                })
+                .unwrap(),
+                funds: vec![],
+            })]
+        );
+        assert_eq!(tracked_staking_balance(&deps), tracked);
+
+        // any other token is swept whole
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("creator", &[]),
+            sweep(SweepToken::Native {
+                denom: "uatom".to_string(),
+            }),
+        )
+        .unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send {
+                to_address: "treasury".to_string(),
+                amount: vec![coin(50, "uatom")],
+            })]
+        );
+    }
 
+    #[test]
+    fn native_mode_locks_and_pays_out_coins() {
+        let mut deps = mock_dependencies_with_balance(&[]);
+        let info = mock_info("creator", &[]);
         let msg = InstantiateMsg {
-            name: "xQtum".to_string(),
-            symbol: "xQtum".to_string(),
-            decimals: 6,
-            long_period:  30 * 14400,
-            short_period: 15 * 14400,
-            long_tax: 2,
-            short_tax: 3,
-            penalty: 2,
-            stake_denom: "qtum".to_string(),
-            staking_token_address: Addr::unchecked("qtum").to_string()
+            staking_mode: Some(StakingMode::Native {}),
+            ..default_instantiate()
         };
-        let info = mock_info(&creator, &[]);
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
 
-        let res =  instantiate(deps.as_mut(), mock_env(), info, msg.clone()).unwrap();
-        assert_eq!(0, res.messages.len());
+        // the cw20 hooks are off in native mode
+        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "addr0000".to_string(),
+            amount: Uint128::new(1000),
+            msg: to_binary(&Cw20HookMsg::Lock {
+                tier: "long".to_string(),
+            })
+            .unwrap(),
+        });
+        let err = execute(deps.as_mut(), mock_env(), mock_info("qtum", &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::InvalidStakingToken {});
 
+        let lock = ExecuteMsg::Lock {
+            tier: "long".to_string(),
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("addr0000", &coins(1000, "uatom")),
+            lock.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::Payment(PaymentError::MissingDenom("qtum".to_string()))
+        );
 
-        let token = query_token_info(deps.as_ref()).unwrap();
+        // the bank moves the coins before the contract runs
+        deps.querier.update_balance(MOCK_CONTRACT_ADDR, coins(1000, "qtum"));
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("addr0000", &coins(1000, "qtum")),
+            lock,
+        )
+        .unwrap();
+        assert_eq!(get_attribute(&res, "minted"), "980");
+        assert_eq!(
+            query_investment(deps.as_ref(), mock_env()).unwrap().staking_mode,
+            StakingMode::Native {}
+        );
 
-        assert_eq!(&token.name, &msg.name);
-        assert_eq!(&token.symbol, &msg.symbol);
-        assert_eq!(token.decimals, msg.decimals);
-        assert_eq!(token.total_supply, Uint128::zero());
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(30 * 14400);
+        let msg = ExecuteMsg::UnLock {
+            amount: Uint128::new(980),
+            token_id: None,
+            position_ids: None,
+            strategy: None,
+        };
+        execute(deps.as_mut(), env.clone(), mock_info("addr0000", &[]), msg).unwrap();
+        let res = execute(deps.as_mut(), env, mock_info("addr0000", &[]), ExecuteMsg::Claim {})
+            .unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(BankMsg::Send {
+                to_address: "addr0000".to_string(),
+                amount: coins(980, "qtum"),
+            })]
+        );
 
-        // no balance
-        assert_eq!(get_balance(deps.as_ref(), &creator), Uint128::zero());
+        // and native locks are off in cw20 mode
+        let mut deps = mock_dependencies_with_balance(&[]);
+        let info = mock_info("creator", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, default_instantiate()).unwrap();
+        let msg = ExecuteMsg::Lock {
+            tier: "short".to_string(),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("addr0000", &coins(1000, "qtum")), msg)
+            .unwrap_err();
+        assert_eq!(err, ContractError::InvalidStakingToken {});
+    }
 
-        let staking_info = query_investment(deps.as_ref()).unwrap();
-        assert_eq!(&staking_info.owner, &creator);
-        assert_eq!(staking_info.staked_tokens, coin(0, "qtum"));
-        assert_eq!(staking_info.tax, LockTax {long: Decimal::percent(msg.long_tax), short: Decimal::percent(msg.short_tax)});
-        assert_eq!(staking_info.token_supply, Uint128::zero());
+    // delegating_app is a chain with a single validator and a contract delegating to it,
+    // addr0000 and addr0001 hold 1000 ustake each
+    // staking_app is a chain with validator1, unbonding in 60 seconds, where addr0000 and
+    // addr0001 hold 1000 ustake each. It returns the code id of this contract
+    fn staking_app() -> (App, u64) {
+        let validator = Validator {
+            address: "validator1".to_string(),
+            commission: Decimal::zero(),
+            max_commission: Decimal::one(),
+            max_change_rate: Decimal::one(),
+        };
+        let mut app = App::new(|router, api, storage| {
+            let staking = MultiTestStakingInfo {
+                bonded_denom: "ustake".to_string(),
+                unbonding_time: 60,
+                apr: Decimal::percent(10),
+            };
+            router.staking.setup(storage, staking).unwrap();
+            router
+                .staking
+                .add_validator(api, storage, &mock_env().block, validator)
+                .unwrap();
+            for holder in ["addr0000", "addr0001"] {
+                router
+                    .bank
+                    .init_balance(storage, &Addr::unchecked(holder), coins(1000, "ustake"))
+                    .unwrap();
+            }
+        });
+        let code_id = app.store_code(Box::new(ContractWrapper::new(execute, instantiate, query)));
+        (app, code_id)
+    }
 
-    
+    // delegating_instantiate delegates the locked ustake to `validator`
+    fn delegating_instantiate(validator: &str) -> InstantiateMsg {
+        InstantiateMsg {
+            stake_denom: "ustake".to_string(),
+            staking_mode: Some(StakingMode::Native {}),
+            delegation: Some(DelegationConfig {
+                validators: vec![validator.to_string()],
+                unbonding_period: 60,
+            }),
+            ..default_instantiate()
+        }
+    }
+
+    fn delegating_app() -> (App, Addr) {
+        let (mut app, code_id) = staking_app();
+        let msg = delegating_instantiate("validator1");
+        let contract = app
+            .instantiate_contract(code_id, Addr::unchecked("creator"), &msg, &[], "staking", None)
+            .unwrap();
+        (app, contract)
     }
 
     #[test]
-    fn lock() {
+    fn delegated_claims_wait_for_the_unbonding_period() {
+        let (mut app, contract) = delegating_app();
+        let lock = ExecuteMsg::Lock {
+            tier: "long".to_string(),
+        };
+        app.execute_contract(
+            Addr::unchecked("addr0000"),
+            contract.clone(),
+            &lock,
+            &coins(1000, "ustake"),
+        )
+        .unwrap();
+        let msg = ExecuteMsg::UnLock {
+            amount: Uint128::new(980),
+            token_id: None,
+            position_ids: None,
+            strategy: None,
+        };
+        app.execute_contract(Addr::unchecked("addr0000"), contract.clone(), &msg, &[])
+            .unwrap();
+
+        // a single claim, released once the 60 seconds of unbonding are over
+        let claims: ClaimsResponse = app
+            .wrap()
+            .query_wasm_smart(
+                &contract,
+                &QueryMsg::Claims {
+                    address: "addr0000".to_string(),
+                },
+            )
+            .unwrap();
+        let release = app.block_info().time.plus_seconds(60);
+        assert_eq!(claims.claims.len(), 1);
+        assert_eq!(claims.claims[0].release_at, Expiration::AtTime(release));
+        let amount = claims.claims[0].amount;
+
+        let claim = |app: &mut App| {
+            app.execute_contract(Addr::unchecked("addr0000"), contract.clone(), &ExecuteMsg::Claim {}, &[])
+        };
+        app.update_block(|block| block.time = block.time.plus_seconds(59));
+        let err = claim(&mut app).unwrap_err();
+        assert_eq!(err.downcast::<ContractError>().unwrap(), ContractError::NothingToClaim {});
+
+        app.update_block(|block| block.time = block.time.plus_seconds(1));
+        app.sudo(StakingSudo::ProcessQueue {}.into()).unwrap();
+        claim(&mut app).unwrap();
+        assert_eq!(
+            app.wrap().query_balance("addr0000", "ustake").unwrap(),
+            coin(amount.u128(), "ustake")
+        );
+        let err = claim(&mut app).unwrap_err();
+        assert_eq!(err.downcast::<ContractError>().unwrap(), ContractError::NothingToClaim {});
+    }
+
+    #[test]
+    fn slashed_delegation_is_written_off() {
+        let (mut app, contract) = delegating_app();
+        let lock = ExecuteMsg::Lock {
+            tier: "long".to_string(),
+        };
+        app.execute_contract(
+            Addr::unchecked("addr0000"),
+            contract.clone(),
+            &lock,
+            &coins(1000, "ustake"),
+        )
+        .unwrap();
 
+        let slash = StakingSudo::Slash {
+            validator: "validator1".to_string(),
+            percentage: Decimal::percent(10),
+        };
+        app.sudo(slash.into()).unwrap();
+
+        // the holders share the loss, and the next lock buys at the lower exchange rate
+        let res = app
+            .execute_contract(
+                Addr::unchecked("addr0001"),
+                contract.clone(),
+                &lock,
+                &coins(1000, "ustake"),
+            )
+            .unwrap();
+        let minted = res
+            .custom_attrs(1)
+            .iter()
+            .find(|a| a.key == "minted")
+            .unwrap()
+            .value
+            .clone();
+        assert_eq!(minted, "1088");
+        let investment: InvestmentResponse = app
+            .wrap()
+            .query_wasm_smart(&contract, &QueryMsg::Investment {})
+            .unwrap();
+        assert_eq!(investment.staked_tokens, coin(882 + 980, "ustake"));
+        assert_eq!(investment.token_supply, Uint128::new(980 + 1088));
+    }
+
+    #[test]
+    fn delegation_rewards_are_not_swept() {
+        let (mut app, contract) = delegating_app();
+        let lock = ExecuteMsg::Lock {
+            tier: "long".to_string(),
+        };
+        app.execute_contract(
+            Addr::unchecked("addr0000"),
+            contract.clone(),
+            &lock,
+            &coins(1000, "ustake"),
+        )
+        .unwrap();
+
+        // the withdrawn rewards wait on the contract until they are reinvested
+        app.update_block(|block| block.time = block.time.plus_seconds(SECONDS_PER_YEAR));
+        let withdraw = DistributionMsg::WithdrawDelegatorReward {
+            validator: "validator1".to_string(),
+        };
+        app.execute(contract.clone(), withdraw.into()).unwrap();
+        assert_eq!(
+            app.wrap().query_balance(&contract, "ustake").unwrap(),
+            coin(20 + 98, "ustake")
+        );
+
+        let msg = ExecuteMsg::Sweep {
+            token: SweepToken::Native {
+                denom: "ustake".to_string(),
+            },
+            recipient: "creator".to_string(),
+        };
+        let err = app
+            .execute_contract(Addr::unchecked("creator"), contract, &msg, &[])
+            .unwrap_err();
+        assert_eq!(err.downcast::<ContractError>().unwrap(), ContractError::CannotSweepStake {});
+    }
+
+    #[test]
+    fn delegated_penalties_are_fees_once_unbonded() {
+        let (mut app, contract) = delegating_app();
+        let lock = ExecuteMsg::Lock {
+            tier: "long".to_string(),
+        };
+        app.execute_contract(
+            Addr::unchecked("addr0000"),
+            contract.clone(),
+            &lock,
+            &coins(1000, "ustake"),
+        )
+        .unwrap();
+        let msg = ExecuteMsg::UnLock {
+            amount: Uint128::new(980),
+            token_id: None,
+            position_ids: None,
+            strategy: None,
+        };
+        app.execute_contract(Addr::unchecked("addr0000"), contract.clone(), &msg, &[])
+            .unwrap();
+
+        // the early exit penalty is still unbonding, only the lock tax can be withdrawn
+        let fees: FeesResponse = app
+            .wrap()
+            .query_wasm_smart(&contract, &QueryMsg::Fees {})
+            .unwrap();
+        assert_eq!(fees.available, Uint128::new(20));
+        assert_eq!(fees.unbonding, Uint128::new(19));
+        let withdraw = |amount: u128| ExecuteMsg::WithdrawFees {
+            recipient: "creator".to_string(),
+            amount: Uint128::new(amount),
+        };
+        let err = app
+            .execute_contract(Addr::unchecked("creator"), contract.clone(), &withdraw(20 + 19), &[])
+            .unwrap_err();
+        assert_eq!(
+            err.downcast::<ContractError>().unwrap(),
+            ContractError::InsufficientFees {
+                available: Uint128::new(20)
+            }
+        );
+
+        app.update_block(|block| block.time = block.time.plus_seconds(60));
+        app.sudo(StakingSudo::ProcessQueue {}.into()).unwrap();
+        let fees: FeesResponse = app
+            .wrap()
+            .query_wasm_smart(&contract, &QueryMsg::Fees {})
+            .unwrap();
+        assert_eq!(fees.available, Uint128::new(20 + 19));
+        assert_eq!(fees.unbonding, Uint128::zero());
+        app.execute_contract(Addr::unchecked("creator"), contract.clone(), &withdraw(20 + 19), &[])
+            .unwrap();
+        assert_eq!(
+            app.wrap().query_balance("creator", "ustake").unwrap(),
+            coin(39, "ustake")
+        );
+    }
+
+    #[test]
+    fn delegation_bonds_unbonds_and_reinvests() {
+        // only validators of the chain
+        let (mut app, code_id) = staking_app();
+        let msg = delegating_instantiate("validator2");
+        let err = app
+            .instantiate_contract(code_id, Addr::unchecked("creator"), &msg, &[], "staking", None)
+            .unwrap_err();
+        assert_eq!(
+            err.downcast::<ContractError>().unwrap(),
+            ContractError::NotInValidatorSet {
+                validator: "validator2".to_string()
+            }
+        );
+        let (mut app, contract) = delegating_app();
+
+        // the locked coins are delegated, the 2% tax stays with the contract
+        let msg = ExecuteMsg::Lock {
+            tier: "long".to_string(),
+        };
+        app.execute_contract(
+            Addr::unchecked("addr0000"),
+            contract.clone(),
+            &msg,
+            &coins(1000, "ustake"),
+        )
+        .unwrap();
+        let bond = app
+            .wrap()
+            .query_delegation(&contract, "validator1")
+            .unwrap()
+            .unwrap();
+        assert_eq!(bond.amount, coin(980, "ustake"));
+
+        // a year of rewards is staked again for the holders
+        app.update_block(|block| block.time = block.time.plus_seconds(SECONDS_PER_YEAR));
+        app.execute_contract(Addr::unchecked("anyone"), contract.clone(), &ExecuteMsg::Reinvest {}, &[])
+            .unwrap();
+        let investment: InvestmentResponse = app
+            .wrap()
+            .query_wasm_smart(&contract, &QueryMsg::Investment {})
+            .unwrap();
+        assert_eq!(investment.staked_tokens, coin(1078, "ustake"));
+        let err = app
+            .execute_contract(
+                Addr::unchecked("anyone"),
+                contract.clone(),
+                &ExecuteMsg::_BondAllTokens {},
+                &[],
+            )
+            .unwrap_err();
+        assert_eq!(err.downcast::<ContractError>().unwrap(), ContractError::Unauthorized {});
+
+        // unlocking undelegates, and the claim waits for the unbonding period
+        let msg = ExecuteMsg::UnLock {
+            amount: Uint128::new(980),
+            token_id: None,
+            position_ids: None,
+            strategy: None,
+        };
+        app.execute_contract(Addr::unchecked("addr0000"), contract.clone(), &msg, &[])
+            .unwrap();
+        // only the token rounded away to the virtual share stays delegated
+        let delegation = app.wrap().query_delegation(&contract, "validator1").unwrap();
+        assert_eq!(delegation.unwrap().amount, coin(1, "ustake"));
+        let err = app
+            .execute_contract(Addr::unchecked("addr0000"), contract.clone(), &ExecuteMsg::Claim {}, &[])
+            .unwrap_err();
+        assert_eq!(err.downcast::<ContractError>().unwrap(), ContractError::NothingToClaim {});
+
+        app.update_block(|block| block.time = block.time.plus_seconds(60));
+        app.sudo(StakingSudo::ProcessQueue {}.into()).unwrap();
+        app.execute_contract(Addr::unchecked("addr0000"), contract.clone(), &ExecuteMsg::Claim {}, &[])
+            .unwrap();
+        assert_eq!(
+            app.wrap().query_balance("addr0000", "ustake").unwrap(),
+            coin(1077, "ustake")
+        );
+        // the tax is left for the owner
+        assert_eq!(
+            app.wrap().query_balance(&contract, "ustake").unwrap(),
+            coin(20, "ustake")
+        );
+    }
+
+    #[test]
+    fn locked_transfers_wait_for_maturity() {
         let mut deps = mock_dependencies_with_balance(&[]);
+        let info = mock_info("creator", &[]);
+        let msg = InstantiateMsg {
+            lock_transfers: Some(true),
+            ..default_instantiate()
+        };
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        lock_tokens(&mut deps, mock_env(), "addr0000", 1000, "long");
 
+        let transferable =
+            query_transferable_balance(deps.as_ref(), mock_env(), "addr0000".to_string()).unwrap();
+        assert_eq!(
+            transferable,
+            TransferableBalanceResponse {
+                transferable: Uint128::zero(),
+                locked: Uint128::new(980),
+            }
+        );
 
-        let instantiate_msg = default_instantiate();
-        let info = mock_info("addr0000", &[]);
-        let _res = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+        let transfer = ExecuteMsg::Transfer {
+            recipient: "addr0001".to_string(),
+            amount: Uint128::new(500),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("addr0000", &[]), transfer.clone())
+            .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::LockedBalance {
+                transferable: Uint128::zero()
+            }
+        );
 
-        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+        // an allowance doesn't get around it either
+        let msg = ExecuteMsg::IncreaseAllowance {
+            spender: "spender".to_string(),
+            amount: Uint128::new(500),
+            expires: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("addr0000", &[]), msg).unwrap();
+        let msg = ExecuteMsg::TransferFrom {
+            owner: "addr0000".to_string(),
+            recipient: "addr0001".to_string(),
+            amount: Uint128::new(500),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("spender", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::LockedBalance { .. }));
+
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(30 * 14400);
+        execute(deps.as_mut(), env.clone(), mock_info("addr0000", &[]), transfer).unwrap();
+        assert_eq!(get_balance(deps.as_ref(), "addr0001"), Uint128::new(500));
+        let transferable =
+            query_transferable_balance(deps.as_ref(), env, "addr0000".to_string()).unwrap();
+        assert_eq!(transferable.transferable, Uint128::new(480));
+    }
+
+    #[test]
+    fn transferred_tokens_keep_their_lock() {
+        let mut deps = mock_dependencies_with_balance(&[]);
+        instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), default_instantiate())
+            .unwrap();
+        lock_tokens(&mut deps, mock_env(), "addr0000", 1000, "long");
+
+        // the backing moves along with the tokens
+        let msg = ExecuteMsg::Transfer {
+            recipient: "alt".to_string(),
+            amount: Uint128::new(979),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("addr0000", &[]), msg).unwrap();
+        let kept = positions().load(&deps.storage, 1).unwrap();
+        assert_eq!(kept.owner, Addr::unchecked("addr0000"));
+        assert_eq!(kept.amount, Uint128::new(1));
+        let moved = positions().load(&deps.storage, 2).unwrap();
+        assert_eq!(moved.owner, Addr::unchecked("alt"));
+        assert_eq!(moved.amount, Uint128::new(979));
+        assert_eq!(moved.release, kept.release);
+
+        // so unlocking at the new address still pays the early exit penalty
+        sync_staking_balance(&mut deps);
+        let msg = ExecuteMsg::UnLock {
+            amount: Uint128::new(979),
+            token_id: None,
+            position_ids: None,
+            strategy: None,
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("alt", &[]), msg).unwrap();
+        assert_eq!(get_attribute(&res, "early_exit"), "true");
+        assert_eq!(get_attribute(&res, "penalty"), "19");
+        assert_eq!(get_attribute(&res, "unlocked"), "960");
+    }
+
+    #[test]
+    fn position_receipts_trade_as_nfts() {
+        let mut deps = mock_dependencies_with_balance(&[]);
+        let info = mock_info("creator", &[]);
+        let msg = InstantiateMsg {
+            lock_transfers: Some(true),
+            ..default_instantiate()
+        };
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        lock_tokens(&mut deps, mock_env(), "addr0000", 1000, "long");
+        lock_tokens(&mut deps, mock_env(), "addr0000", 1000, "short");
+
+        let info = query_nft_info(deps.as_ref(), "1".to_string()).unwrap();
+        assert_eq!(
+            info.extension,
+            PositionReceipt {
+                amount: Uint128::new(980),
+                tier: "long".to_string(),
+                start: mock_env().block.time,
+                release: Expiration::AtTime(mock_env().block.time.plus_seconds(30 * 14400)),
+            }
+        );
+        assert_eq!(query_num_tokens(deps.as_ref()).unwrap().count, 2);
+
+        // only the owner or an approved spender moves a receipt
+        let transfer = ExecuteMsg::TransferNft {
+            recipient: "buyer".to_string(),
+            token_id: "1".to_string(),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("buyer", &[]), transfer.clone())
+            .unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        let msg = ExecuteMsg::Approve {
+            spender: "buyer".to_string(),
+            token_id: "1".to_string(),
+            expires: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("addr0000", &[]), msg).unwrap();
+        let owner = query_owner_of(deps.as_ref(), mock_env(), "1".to_string(), false).unwrap();
+        assert_eq!(owner.approvals.len(), 1);
+
+        // with transfers locked, the receipt can't move before the position matured
+        let err = execute(deps.as_mut(), mock_env(), mock_info("buyer", &[]), transfer.clone())
+            .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::UnmaturedPosition {
+                token_id: "1".to_string()
+            }
+        );
+        let send = ExecuteMsg::SendNft {
+            contract: "market".to_string(),
+            token_id: "2".to_string(),
+            msg: to_binary("sell").unwrap(),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("addr0000", &[]), send.clone())
+            .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::UnmaturedPosition {
+                token_id: "2".to_string()
+            }
+        );
+
+        // the matured position moves with its tokens
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(30 * 14400);
+        execute(deps.as_mut(), env.clone(), mock_info("buyer", &[]), transfer).unwrap();
+        assert_eq!(get_balance(deps.as_ref(), "buyer"), Uint128::new(980));
+        assert_eq!(get_balance(deps.as_ref(), "addr0000"), Uint128::new(970));
+        let owner = query_owner_of(deps.as_ref(), env.clone(), "1".to_string(), false).unwrap();
+        assert_eq!(
+            owner,
+            OwnerOfResponse {
+                owner: "buyer".to_string(),
+                approvals: vec![],
+            }
+        );
+        let tokens = query_tokens(deps.as_ref(), Some("addr0000".to_string()), None, None).unwrap();
+        assert_eq!(tokens.tokens, vec!["2".to_string()]);
+
+        let res = execute(deps.as_mut(), env.clone(), mock_info("addr0000", &[]), send).unwrap();
+        let receive = Cw721ReceiveMsg {
             sender: "addr0000".to_string(),
-            amount: Uint128::from(100u128),
-            msg: to_binary(&Cw20HookMsg::Lock {lock_type:LockType::Long {}}).unwrap(),
-        });
+            token_id: "2".to_string(),
+            msg: to_binary("sell").unwrap(),
+        };
+        assert_eq!(res.messages[0].msg, receive.into_cosmos_msg("market").unwrap());
 
-        let info = mock_info("qtum", &[]);
-        let env = mock_env();
-        let _res = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+        // the buyer redeems the receipt at its original maturity
+        let redeem = ExecuteMsg::UnLock {
+            amount: Uint128::new(980),
+            token_id: Some("1".to_string()),
+            position_ids: None,
+            strategy: None,
+        };
+        let msg = ExecuteMsg::UnLock {
+            amount: Uint128::new(100),
+            token_id: Some("1".to_string()),
+            position_ids: None,
+            strategy: None,
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("market", &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        sync_staking_balance(&mut deps);
+        let res = execute(deps.as_mut(), env, mock_info("buyer", &[]), redeem).unwrap();
+        assert_eq!(get_attribute(&res, "early_exit"), "false");
+        assert_eq!(get_attribute(&res, "unlocked"), "980");
+        assert_eq!(
+            query_tokens(deps.as_ref(), None, None, None).unwrap().tokens,
+            vec!["2".to_string()]
+        );
+    }
 
+    #[test]
+    fn burnt_tokens_release_their_positions() {
+        let mut deps = mock_dependencies_with_balance(&[]);
+        let info = mock_info("creator", &[]);
+        let msg = InstantiateMsg {
+            tiers: long_short_tiers(2, 3, 2, Some(Decimal::percent(150))),
+            lock_transfers: Some(true),
+            ..default_instantiate()
+        };
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        lock_tokens(&mut deps, mock_env(), "addr0000", 1000, "short");
+        lock_tokens(&mut deps, mock_env(), "addr0000", 1000, "long");
+        assert_eq!(BOOST.load(&deps.storage).unwrap().weight, Uint128::new(490));
 
-        let supply_info = TOTAL_SUPPLY.load(deps.as_ref().storage).unwrap();
+        // once the short position matured, burning its tokens drops it
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(16 * 14400);
+        let burn = |amount: u128| ExecuteMsg::Burn {
+            amount: Uint128::new(amount),
+        };
+        execute(deps.as_mut(), env.clone(), mock_info("addr0000", &[]), burn(970)).unwrap();
+        assert_eq!(query_num_tokens(deps.as_ref()).unwrap().count, 1);
+        assert_eq!(BOOST.load(&deps.storage).unwrap().weight, Uint128::new(490));
 
-        assert_eq!(supply_info.issued, Uint128::new(98) )
+        // the long position is still locked, and shrinks with the burnt tokens
+        let transfer = ExecuteMsg::Transfer {
+            recipient: "buyer".to_string(),
+            amount: Uint128::new(1),
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("addr0000", &[]), transfer)
+            .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::LockedBalance {
+                transferable: Uint128::zero()
+            }
+        );
+        execute(deps.as_mut(), env.clone(), mock_info("addr0000", &[]), burn(500)).unwrap();
+        let position = positions().load(&deps.storage, 2).unwrap();
+        assert_eq!(position.amount, Uint128::new(480));
+        assert_eq!(BOOST.load(&deps.storage).unwrap().weight, Uint128::new(240));
+
+        execute(deps.as_mut(), env, mock_info("addr0000", &[]), burn(480)).unwrap();
+        assert_eq!(query_num_tokens(deps.as_ref()).unwrap().count, 0);
+        assert_eq!(BOOST.load(&deps.storage).unwrap().weight, Uint128::zero());
+    }
+
+    #[test]
+    fn extend_lock_charges_the_tax_difference() {
+        let mut deps = mock_dependencies_with_balance(&[]);
+        let info = mock_info("creator", &[]);
+        let msg = InstantiateMsg {
+            tiers: long_short_tiers(5, 1, 2, Some(Decimal::percent(150))),
+            ..default_instantiate()
+        };
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        lock_tokens(&mut deps, mock_env(), "addr0000", 1000, "short");
 
+        let extend = |tier: &str| ExecuteMsg::ExtendLock {
+            position_id: 1,
+            tier: tier.to_string(),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("addr0001", &[]), extend("long"))
+            .unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        // 4% more tax on the 990 tokens the position is worth
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(86400);
+        let res = execute(deps.as_mut(), env.clone(), mock_info("addr0000", &[]), extend("long"))
+            .unwrap();
+        assert_eq!(get_attribute(&res, "tax"), "39");
+        assert_eq!(get_attribute(&res, "burnt"), "39");
+        assert_eq!(get_balance(deps.as_ref(), "addr0000"), Uint128::new(951));
+        let position = positions().load(&deps.storage, 1).unwrap();
+        assert_eq!(position.tier, "long");
+        assert_eq!(position.amount, Uint128::new(951));
+        assert_eq!(position.boost, Uint128::new(475));
+        assert_eq!(
+            position.release,
+            Expiration::AtTime(env.block.time.plus_seconds(30 * 14400))
+        );
+        let supply = TOTAL_SUPPLY.load(&deps.storage).unwrap();
+        assert_eq!(supply.fees, Uint128::new(10 + 39));
+        assert_eq!(BOOST.load(&deps.storage).unwrap().weight, Uint128::new(475));
+
+        let err = execute(deps.as_mut(), env.clone(), mock_info("addr0000", &[]), extend("short"))
+            .unwrap_err();
+        assert_eq!(err, ContractError::CannotShortenLock {});
+
+        // renewing a matured lock is free
+        env.block.time = env.block.time.plus_seconds(30 * 14400);
+        let res = execute(deps.as_mut(), env.clone(), mock_info("addr0000", &[]), extend("long"))
+            .unwrap();
+        assert_eq!(get_attribute(&res, "tax"), "0");
+        let positions =
+            query_positions(deps.as_ref(), env.clone(), "addr0000".to_string(), None, None)
+                .unwrap()
+                .positions;
+        assert!(!positions[0].matured);
+
+        // the position keeps the terms it was extended with when the tiers change
+        let set_tier = |tier: TierConfig| ExecuteMsg::SetTier { tier };
+        let tiers = [
+            tier_config("long", 10 * 14400, Rate::Percent(5), Rate::Percent(2)),
+            tier_config("mid", 40 * 14400, Rate::Percent(1), Rate::Percent(2)),
+            tier_config("short", 20 * 14400, Rate::Percent(5), Rate::Percent(2)),
+        ];
+        for tier in tiers {
+            execute(deps.as_mut(), env.clone(), mock_info("creator", &[]), set_tier(tier))
+                .unwrap();
+        }
+        env.block.time = env.block.time.plus_seconds(15 * 14400);
+        let err = execute(deps.as_mut(), env.clone(), mock_info("addr0000", &[]), extend("short"))
+            .unwrap_err();
+        assert_eq!(err, ContractError::CannotShortenLock {});
+        let err = execute(deps.as_mut(), env, mock_info("addr0000", &[]), extend("mid"))
+            .unwrap_err();
+        assert_eq!(err, ContractError::CannotLowerTax {});
+    }
+
+    #[test]
+    fn fifo_unlock_spends_unbacked_tokens_first() {
+        let mut deps = mock_dependencies_with_balance(&[]);
+        let info = mock_info("creator", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, default_instantiate()).unwrap();
+
+        // a holder from before positions were recorded locks once more
+        let holder = Addr::unchecked("holder");
+        BALANCES
+            .save(deps.as_mut().storage, &holder, &Uint128::new(1000))
+            .unwrap();
+        cw20_base::state::TOKEN_INFO
+            .update(deps.as_mut().storage, |mut info| -> StdResult<_> {
+                info.total_supply = Uint128::new(1000);
+                Ok(info)
+            })
+            .unwrap();
+        let supply = Supply {
+            issued: Uint128::new(1000),
+            locked: Uint128::new(1000),
+            ..Supply::default()
+        };
+        TOTAL_SUPPLY.save(deps.as_mut().storage, &supply).unwrap();
+        deposit_staking_balance(&mut deps, 1000);
+        lock_tokens(&mut deps, mock_env(), "holder", 1000, "long");
+
+        // the older balance goes first and pays no penalty
+        sync_staking_balance(&mut deps);
+        let msg = ExecuteMsg::UnLock {
+            amount: Uint128::new(500),
+            token_id: None,
+            position_ids: None,
+            strategy: None,
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("holder", &[]), msg).unwrap();
+        assert_eq!(get_attribute(&res, "matured"), "500");
+        assert_eq!(get_attribute(&res, "penalty"), "0");
+        let position = positions().load(&deps.storage, 1).unwrap();
+        assert_eq!(position.amount, Uint128::new(980));
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn unlock_picks_positions_by_id_or_strategy() {
+        let mut deps = mock_dependencies_with_balance(&[]);
+        let info = mock_info("creator", &[]);
+        let msg = InstantiateMsg {
+            tiers: long_short_tiers(0, 0, 10, None),
+            ..default_instantiate()
+        };
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        lock_tokens(&mut deps, mock_env(), "addr0000", 1000, "long");
+        lock_tokens(&mut deps, mock_env(), "addr0000", 1000, "short");
+        lock_tokens(&mut deps, mock_env(), "addr0001", 300, "short");
+
+        // the short positions are matured, the long one is not
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(16 * 14400);
+        let unlock = |amount: u128, position_ids: Option<Vec<u64>>, strategy| ExecuteMsg::UnLock {
+            amount: Uint128::new(amount),
+            token_id: None,
+            position_ids,
+            strategy,
+        };
+        let parts = |res: &Response, key: &str| -> Vec<String> {
+            res.attributes
+                .iter()
+                .filter(|a| a.key == key)
+                .map(|a| a.value.clone())
+                .collect()
+        };
+
+        let msg = ExecuteMsg::UnLock {
+            amount: Uint128::new(100),
+            token_id: Some("1".to_string()),
+            position_ids: None,
+            strategy: Some(UnlockStrategy::Lifo {}),
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("addr0000", &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::InvalidPositionSelection {});
+
+        // newest first takes from the matured short position
+        let msg = unlock(100, None, Some(UnlockStrategy::Lifo {}));
+        let res = execute(deps.as_mut(), env.clone(), mock_info("addr0000", &[]), msg).unwrap();
+        assert_eq!(parts(&res, "position_id"), vec!["2"]);
+        assert_eq!(get_attribute(&res, "penalty"), "0");
+
+        // matured backing received by transfer is spent before the unmatured position
+        let msg = ExecuteMsg::Transfer {
+            recipient: "addr0000".to_string(),
+            amount: Uint128::new(100),
+        };
+        execute(deps.as_mut(), env.clone(), mock_info("addr0001", &[]), msg).unwrap();
+        let msg = unlock(1100, None, Some(UnlockStrategy::MaturedFirst {}));
+        let res = execute(deps.as_mut(), env.clone(), mock_info("addr0000", &[]), msg).unwrap();
+        assert_eq!(parts(&res, "position_id"), vec!["2", "4", "1"]);
+        assert_eq!(parts(&res, "position_burnt"), vec!["900", "100", "100"]);
+        assert_eq!(parts(&res, "position_penalty"), vec!["0", "0", "10"]);
+        assert_eq!(get_attribute(&res, "matured"), "1000");
+        assert_eq!(get_attribute(&res, "unmatured"), "100");
+        assert_eq!(get_attribute(&res, "penalty"), "10");
+
+        // listed positions are used in order, and must cover the amount
+        let msg = unlock(450, Some(vec![1]), None);
+        let res = execute(deps.as_mut(), env.clone(), mock_info("addr0000", &[]), msg).unwrap();
+        assert_eq!(parts(&res, "position_value"), vec!["450"]);
+        assert_eq!(parts(&res, "position_penalty"), vec!["45"]);
+        assert_eq!(positions().load(&deps.storage, 1).unwrap().amount, Uint128::new(450));
+
+        let msg = ExecuteMsg::Transfer {
+            recipient: "addr0000".to_string(),
+            amount: Uint128::new(100),
+        };
+        execute(deps.as_mut(), env.clone(), mock_info("addr0001", &[]), msg).unwrap();
+        let msg = unlock(451, Some(vec![1, 1]), None);
+        let err = execute(deps.as_mut(), env.clone(), mock_info("addr0000", &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::ExceedsPosition { amount: Uint128::new(450) });
+
+        let msg = unlock(50, Some(vec![1]), None);
+        let err = execute(deps.as_mut(), env, mock_info("addr0001", &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+    }
+}
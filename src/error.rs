@@ -1,4 +1,5 @@
-use cosmwasm_std::{StdError, Uint128};
+use cosmwasm_std::{Decimal, StdError, Uint128};
+use cw_utils::PaymentError;
 use thiserror::Error;
 
 #[derive(Error, Debug, PartialEq)]
@@ -6,6 +7,12 @@ pub enum ContractError {
     #[error("{0}")]
     Std(#[from] StdError),
 
+    #[error("{0}")]
+    Payment(#[from] PaymentError),
+
+    #[error("Semver parsing error: {0}")]
+    SemVer(String),
+
     #[error("Unauthorized Token")]
     Unauthorized {},
 
@@ -17,9 +24,96 @@ pub enum ContractError {
     #[error("Invalid Staking token")]
     InvalidStakingToken {},
 
+    #[error("Invalid reward token")]
+    InvalidRewardToken {},
+
+    #[error("Reward token is already set")]
+    RewardTokenAlreadySet {},
+
     #[error("Stored locked {stored}, but query locked {queried}")]
     LockedMismatch { stored: Uint128, queried: Uint128 },
 
+    #[error("No claims that can be released currently")]
+    NothingToClaim {},
+
+    #[error("Cannot withdraw more than the {available} collected fees")]
+    InsufficientFees { available: Uint128 },
+
+    #[error("The contract holds nothing beyond what it owes")]
+    NothingToSweep {},
+
+    #[error("Staking coins are reinvested for the holders while delegating")]
+    CannotSweepStake {},
+
+    #[error("Position {token_id} not found")]
+    PositionNotFound { token_id: String },
+
+    #[error("A lock can only be extended")]
+    CannotShortenLock {},
+
+    #[error("A lock can't be extended with a lower tax than it paid")]
+    CannotLowerTax {},
+
+    #[error("Position only backs {amount} tokens")]
+    ExceedsPosition { amount: Uint128 },
+
+    #[error("Set only one of token_id, position_ids and strategy")]
+    InvalidPositionSelection {},
+
+    #[error("Only {transferable} tokens are transferable, the rest backs unmatured positions")]
+    LockedBalance { transferable: Uint128 },
+
+    #[error("Position {token_id} has not matured, its receipt can't be transferred")]
+    UnmaturedPosition { token_id: String },
+
+    #[error("Delegating needs native staking mode")]
+    DelegationNeedsNativeMode {},
+
+    #[error("The locked tokens are not delegated")]
+    NotDelegating {},
+
+    #[error("No validators to delegate to")]
+    NoValidators {},
+
+    #[error("Validator '{validator}' not in current validator set")]
+    NotInValidatorSet { validator: String },
+
+    #[error("Different denominations in bonds: '{denom1}' vs. '{denom2}'")]
+    DifferentBondDenom { denom1: String, denom2: String },
+
+    #[error("No ownership transfer is pending")]
+    NoPendingOwner {},
+
+    #[error("Ownership proposal is expired")]
+    OwnershipProposalExpired {},
+
+    #[error("{name} of {rate} is over 100%")]
+    InvalidRate { name: String, rate: Decimal },
+
+    #[error("{name} of {weight} is below 1")]
+    InvalidWeight { name: String, weight: Decimal },
+
+    #[error("Lock period of tier '{id}' must not be zero")]
+    InvalidPeriod { id: String },
+
+    #[error("Tier '{id}' not found")]
+    TierNotFound { id: String },
+
+    #[error("Tier id must not be empty")]
+    EmptyTierId {},
+
+    #[error("Tier '{id}' is listed more than once")]
+    DuplicateTier { id: String },
+
+    #[error("Decimals {decimals} must not exceed 18")]
+    InvalidDecimals { decimals: u8 },
+
+    #[error("No derivative tokens issued to distribute rewards to")]
+    NoShares {},
+
+    #[error("Reward schedule must end after it starts and after the current block")]
+    InvalidRewardSchedule {},
+
     #[error("No {denom} tokens sent")]
     EmptyBalance { denom: String },
 
@@ -44,6 +138,15 @@ pub enum ContractError {
 
     #[error("Duplicate initial balance addresses")]
     DuplicateInitialBalanceAddresses {},
+
+    #[error("Cannot migrate from {stored} to {current}")]
+    CannotMigrate { stored: String, current: String },
+}
+
+impl From<semver::Error> for ContractError {
+    fn from(err: semver::Error) -> Self {
+        Self::SemVer(err.to_string())
+    }
 }
 
 impl From<cw20_base::ContractError> for ContractError {
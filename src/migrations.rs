@@ -0,0 +1,121 @@
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{Addr, CanonicalAddr, Decimal, DepsMut, Env, StdError, Uint128};
+use cw_storage_plus::Item;
+use cw_utils::Duration;
+use semver::Version;
+
+use crate::error::ContractError;
+use crate::msg::{StakingMode, SurplusPolicy};
+use crate::state::{
+    Boost, Fees, LockTier, RewardPool, StakingInfo, Supply, BOOST, FEES, REWARD_POOL,
+    STAKING_INFO, TIERS, TOTAL_SUPPLY,
+};
+
+type Migration = fn(DepsMut, &Env) -> Result<(), ContractError>;
+
+/// MIGRATIONS lists the state transforms in release order. Each one upgrades state written
+/// by any version older than its own, so add a new entry whenever a release changes storage.
+const MIGRATIONS: &[(&str, Migration)] = &[("0.16.0", v0_16::migrate)];
+
+/// run_migrations applies every transform newer than the stored version, oldest first
+pub fn run_migrations(mut deps: DepsMut, env: &Env, stored: &Version) -> Result<(), ContractError> {
+    for (version, migration) in MIGRATIONS {
+        if stored < &version.parse::<Version>()? {
+            migration(deps.branch(), env)?;
+        }
+    }
+    Ok(())
+}
+
+mod v0_16 {
+    use super::*;
+
+    /// Supply as stored by 0.14, where penalties stayed in `locked` and were also counted in `fees`
+    #[cw_serde]
+    struct SupplyV0_14 {
+        issued: Uint128,
+        locked: Uint128,
+        fees: Uint128,
+    }
+
+    #[cw_serde]
+    struct LockPrdV0_14 {
+        long: Duration,
+        short: Duration,
+    }
+
+    #[cw_serde]
+    struct LockTaxV0_14 {
+        long: Decimal,
+        short: Decimal,
+    }
+
+    /// StakingInfo as stored by 0.14, with the fixed long and short locks
+    #[cw_serde]
+    struct StakingInfoV0_14 {
+        owner: Addr,
+        stake_denom: String,
+        staking_token_address: CanonicalAddr,
+        period: LockPrdV0_14,
+        tax: LockTaxV0_14,
+        penalty: Decimal,
+    }
+
+    const SUPPLY_V0_14: Item<SupplyV0_14> = Item::new("total_supply");
+
+    const STAKING_INFO_V0_14: Item<StakingInfoV0_14> = Item::new("staking_info");
+
+    /// 0.16 adds claims, splits fees out of the locked tokens and replaces the fixed long and
+    /// short locks with the tiers "long" and "short". Existing holders need no positions,
+    /// tokens without one unlock as matured.
+    pub fn migrate(deps: DepsMut, _env: &Env) -> Result<(), ContractError> {
+        let old = SUPPLY_V0_14.load(deps.storage)?;
+        let supply = Supply {
+            issued: old.issued,
+            locked: old.locked.checked_sub(old.fees).map_err(StdError::overflow)?,
+            claims: Uint128::zero(),
+            fees: old.fees,
+        };
+        TOTAL_SUPPLY.save(deps.storage, &supply)?;
+        FEES.save(
+            deps.storage,
+            &Fees {
+                tax: Uint128::zero(),
+                penalty: old.fees,
+            },
+        )?;
+
+        let old = STAKING_INFO_V0_14.load(deps.storage)?;
+        let long = LockTier {
+            period: old.period.long,
+            tax: old.tax.long,
+            penalty: old.penalty,
+            weight: Decimal::one(),
+        };
+        let short = LockTier {
+            period: old.period.short,
+            tax: old.tax.short,
+            penalty: old.penalty,
+            weight: Decimal::one(),
+        };
+        TIERS.save(deps.storage, "long", &long)?;
+        TIERS.save(deps.storage, "short", &short)?;
+        STAKING_INFO.save(
+            deps.storage,
+            &StakingInfo {
+                owner: old.owner,
+                stake_denom: old.stake_denom,
+                staking_token_address: old.staking_token_address,
+                reward_token_address: None,
+                surplus_policy: SurplusPolicy::default(),
+                staking_mode: StakingMode::default(),
+                delegation: None,
+                lock_transfers: false,
+            },
+        )?;
+
+        BOOST.save(deps.storage, &Boost::default())?;
+        REWARD_POOL.save(deps.storage, &RewardPool::default())?;
+        Ok(())
+    }
+}
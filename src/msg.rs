@@ -1,12 +1,34 @@
+use std::fmt;
+
 use cosmwasm_schema::{cw_serde, QueryResponses};
 
-use cosmwasm_std::{Binary, Coin, Decimal, Uint128};
+use cosmwasm_std::{to_binary, Binary, Coin, CosmosMsg, Decimal, StdResult, Timestamp, Uint128, WasmMsg};
 use cw20::{Cw20ReceiveMsg, Expiration};
 use cw20::{AllowanceResponse, BalanceResponse, TokenInfoResponse};
 pub use cw_controllers::ClaimsResponse;
-use crate::state::{LockPrd, LockTax};
+use cw_utils::Duration;
+
+use crate::state::Delegation;
 
 
+/// Rate is a tax or penalty, given either as whole percent (`2` is 2%)
+/// or as a decimal string (`"0.0225"` is 2.25%)
+#[cw_serde]
+#[serde(untagged)]
+pub enum Rate {
+    Percent(u64),
+    Decimal(Decimal),
+}
+
+impl From<Rate> for Decimal {
+    fn from(rate: Rate) -> Self {
+        match rate {
+            Rate::Percent(percent) => Decimal::percent(percent),
+            Rate::Decimal(decimal) => decimal,
+        }
+    }
+}
+
 #[cw_serde] 
 pub struct InitMsg {
     /// name of the derivative token
@@ -28,22 +50,112 @@ pub struct InstantiateMsg {
     /// decimal places of the derivative token (for UI)
     pub decimals: u8,
     pub stake_denom: String,
+    /// cw20 staking token, only used in cw20 mode
     pub staking_token_address: String,
+    /// whether stake_denom coins or the cw20 are locked, defaults to cw20
+    pub staking_mode: Option<StakingMode>,
+    /// delegates the locked coins in native mode, they stay in the contract if not set
+    pub delegation: Option<DelegationConfig>,
+    /// lock options offered from the start, with unique ids. More can be added with SetTier
+    pub tiers: Vec<TierConfig>,
+    /// cw20 paid out by ClaimRewards
+    pub reward_token_address: Option<String>,
+    /// what happens to staking tokens sent outside the hooks, defaults to sweep
+    pub surplus_policy: Option<SurplusPolicy>,
+    /// keeps derivative tokens and receipts of unmatured positions from being transferred, defaults
+    /// to false. Transferred tokens carry their positions along either way, so they still pay the penalty
+    pub lock_transfers: Option<bool>,
+}
 
-    pub long_period: u64,
-    pub short_period: u64,
-    pub long_tax: u64,
-    pub short_tax: u64,
-    pub penalty: u64
+/// TierConfig is a lock option, positions keep its period and penalty once opened
+#[cw_serde]
+pub struct TierConfig {
+    /// non-empty name the tier is picked by
+    pub id: String,
+    /// lock period in seconds
+    pub period: u64,
+    /// entry tax taken on lock
+    pub tax: Rate,
+    /// early-exit penalty taken on unlock
+    pub penalty: Rate,
+    /// reward multiplier, defaults to 1
+    pub weight: Option<Decimal>,
 }
 
+#[cw_serde]
+pub struct DelegationConfig {
+    /// locked coins are spread evenly over these validators
+    pub validators: Vec<String>,
+    /// the chain's unbonding time in seconds
+    pub unbonding_period: u64,
+}
+
+#[cw_serde]
+pub struct MigrateMsg {}
+
 #[cw_serde]
 pub enum ExecuteMsg {
 
     Receive(Cw20ReceiveMsg),
-    /// Unbond will "burn" the given amount of derivative tokens and send the unbonded
-    /// staking tokens to the message sender (after exit tax is deducted)
-    UnLock { amount: Uint128 },
+    /// Lock locks the stake_denom coins sent along, in native mode
+    Lock { tier: String },
+    /// UnLock will "burn" the given amount of derivative tokens and create a claim on the
+    /// staking tokens. Positions that have not matured yet pay the early-exit penalty, and the
+    /// claim is released right away, or after the unbonding period when delegating.
+    /// With a token_id the amount is redeemed from that position receipt only, with position_ids
+    /// from those positions in the given order, otherwise positions are picked by strategy
+    /// (FIFO by default). At most one of token_id, position_ids and strategy may be set
+    UnLock {
+        amount: Uint128,
+        token_id: Option<String>,
+        position_ids: Option<Vec<u64>>,
+        strategy: Option<UnlockStrategy>,
+    },
+    /// ExtendLock re-locks one of the caller's positions from now, with the current period and
+    /// penalty of `tier`. Only the difference to the tax the position paid is charged,
+    /// in derivative tokens of the position. A lock can't be shortened or get a lower tax
+    ExtendLock { position_id: u64, tier: String },
+    /// Claim is used to claim the staking tokens that were previously "unlocked"
+    /// once their claims are released
+    Claim {},
+    /// WithdrawFees sends collected tax and penalty to the recipient, delegated ones once they
+    /// are unbonded. Only the owner can call it
+    WithdrawFees { recipient: String, amount: Uint128 },
+    /// Sweep sends the recipient the contract's balance of a token beyond what it owes
+    /// to lockers, claimants and fees. Staking coins held while delegating are rewards
+    /// to reinvest and can't be swept. Only the owner can call it
+    Sweep { token: SweepToken, recipient: String },
+    /// SetTier adds a lock tier or replaces the one with the same id. Only the owner can call it.
+    /// Positions opened before the change keep their period and penalty
+    SetTier { tier: TierConfig },
+    /// RemoveTier stops offering a tier, its open positions are not affected.
+    /// Only the owner can call it
+    RemoveTier { id: String },
+    /// UpdateConfig changes the contract settings. Only the owner can call it
+    UpdateConfig {
+        /// can only be set while no reward token is configured
+        reward_token_address: Option<String>,
+        surplus_policy: Option<SurplusPolicy>,
+        lock_transfers: Option<bool>,
+    },
+    /// ClaimRewards sends the caller the reward tokens earned by holding derivative tokens
+    ClaimRewards {},
+    /// Reinvest withdraws the delegation rewards and stakes them again, raising the value
+    /// of every derivative token. Anyone can call it
+    Reinvest {},
+    /// _BondAllTokens can only be called by the contract itself, after all rewards have been
+    /// withdrawn by Reinvest
+    _BondAllTokens {},
+    /// ProposeNewOwner starts an ownership transfer. Only the owner can call it, and the proposed
+    /// owner must accept before `expiry` (if set). A new proposal replaces the pending one
+    ProposeNewOwner {
+        owner: String,
+        expiry: Option<Expiration>,
+    },
+    /// AcceptOwnership is called by the proposed owner to complete the transfer
+    AcceptOwnership {},
+    /// CancelOwnershipProposal drops the pending proposal. Only the owner can call it
+    CancelOwnershipProposal {},
 
     /// Implements CW20. Transfer is a base message to move tokens to another account without triggering actions
     Transfer { recipient: String, amount: Uint128 },
@@ -89,6 +201,33 @@ pub enum ExecuteMsg {
     },
     /// Implements CW20 "approval" extension. Destroys tokens forever
     BurnFrom { owner: String, amount: Uint128 },
+
+    /// Implements CW721. Every lock position is a receipt with its id as token_id.
+    /// TransferNft moves the position to the recipient, along with the derivative tokens it backs.
+    /// With lock_transfers set, only matured positions can move
+    TransferNft { recipient: String, token_id: String },
+    /// Implements CW721. SendNft moves the position to a contract and triggers an action
+    /// on the receiving contract
+    SendNft {
+        contract: String,
+        token_id: String,
+        msg: Binary,
+    },
+    /// Implements CW721. Allows spender to transfer or send the position
+    Approve {
+        spender: String,
+        token_id: String,
+        expires: Option<Expiration>,
+    },
+    /// Implements CW721. Removes a previously granted approval
+    Revoke { spender: String, token_id: String },
+    /// Implements CW721. Allows operator to transfer or send any position of the owner
+    ApproveAll {
+        operator: String,
+        expires: Option<Expiration>,
+    },
+    /// Implements CW721. Removes a previously granted operator
+    RevokeAll { operator: String },
 }
 
 #[cw_serde]
@@ -98,6 +237,33 @@ pub enum QueryMsg {
     /// Investment shows metadata on the staking info of the contract
     #[returns(InvestmentResponse)]
     Investment {},
+    /// Tiers lists the lock tiers new positions can pick
+    #[returns(TiersResponse)]
+    Tiers {},
+    /// Positions lists the open lock positions of the given address, oldest first
+    #[returns(PositionsResponse)]
+    Positions {
+        address: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+
+    /// Ownership shows the current owner and any pending ownership transfer
+    #[returns(OwnershipResponse)]
+    Ownership {},
+    /// Fees shows the tax and penalty collected by the contract
+    #[returns(FeesResponse)]
+    Fees {},
+    /// PendingRewards shows the reward tokens the address can get with ClaimRewards
+    #[returns(PendingRewardsResponse)]
+    PendingRewards { address: String },
+    /// TransferableBalance shows how many derivative tokens the address can transfer,
+    /// the rest backs unmatured positions
+    #[returns(TransferableBalanceResponse)]
+    TransferableBalance { address: String },
+    /// Claims shows the number of staking tokens this address can access when they are done unlocking
+    #[returns(ClaimsResponse)]
+    Claims { address: String },
 
     /// Implements CW20. Returns the current balance of the given address, 0 if unset.
     #[returns(BalanceResponse)]
@@ -109,6 +275,41 @@ pub enum QueryMsg {
     /// Returns how much spender can use from owner account, 0 if unset.
     #[returns(AllowanceResponse)]
     Allowance { owner: String, spender: String },
+
+    /// Implements CW721. Returns the owner of the position receipt and its approvals
+    #[returns(OwnerOfResponse)]
+    OwnerOf {
+        token_id: String,
+        include_expired: Option<bool>,
+    },
+    /// Implements CW721. Returns the number of open positions
+    #[returns(NumTokensResponse)]
+    NumTokens {},
+    /// Implements CW721. Returns the name and symbol of the contract
+    #[returns(NftContractInfoResponse)]
+    ContractInfo {},
+    /// Implements CW721. Returns the position behind the receipt
+    #[returns(NftInfoResponse)]
+    NftInfo { token_id: String },
+    /// Implements CW721. Returns OwnerOf and NftInfo together
+    #[returns(AllNftInfoResponse)]
+    AllNftInfo {
+        token_id: String,
+        include_expired: Option<bool>,
+    },
+    /// Implements CW721. Lists the position receipts of the owner
+    #[returns(TokensResponse)]
+    Tokens {
+        owner: String,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    /// Implements CW721. Lists all position receipts
+    #[returns(TokensResponse)]
+    AllTokens {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
 }
 
 #[cw_serde]
@@ -120,27 +321,235 @@ pub struct InvestmentResponse {
 
     /// owner created the contract and takes a cut
     pub owner: String,
-    /// this is how much the owner takes as a cut when someone unbonds
+    /// staking tokens per second currently streamed into the pool
+    pub reward_rate: Decimal,
+    /// yearly return the current reward rate gives on staked tokens with weight one
+    pub apr: Decimal,
+    pub surplus_policy: SurplusPolicy,
+    pub lock_transfers: bool,
+    pub staking_mode: StakingMode,
+    pub delegation: Option<Delegation>,
+}
+
+#[cw_serde]
+pub struct TierResponse {
+    pub id: String,
+    pub period: Duration,
+    pub tax: Decimal,
     pub penalty: Decimal,
-    /// This is how much the owner takes as a cut when someone unstake
-    pub tax: LockTax,
-    /// after this perio, you can get back your qtum token, 
-    pub period : LockPrd,
+    pub weight: Decimal,
+}
+
+#[cw_serde]
+pub struct TiersResponse {
+    pub tiers: Vec<TierResponse>,
+}
+
+#[cw_serde]
+pub struct PendingRewardsResponse {
+    pub pending: Uint128,
+    pub reward_token: Option<String>,
+}
+
+#[cw_serde]
+pub struct TransferableBalanceResponse {
+    pub transferable: Uint128,
+    /// balance that can't move until its positions mature
+    pub locked: Uint128,
+}
 
+#[cw_serde]
+pub struct OwnershipResponse {
+    pub owner: String,
+    /// proposed owner that has not accepted yet
+    pub pending_owner: Option<String>,
+    pub pending_expiry: Option<Expiration>,
 }
 
+#[cw_serde]
+pub struct FeesResponse {
+    /// total entry tax collected on lock
+    pub tax: Uint128,
+    /// total early-exit penalty collected on unlock
+    pub penalty: Uint128,
+    /// fees that the owner can still withdraw
+    pub available: Uint128,
+    /// delegated fees still unbonding, they become available once the unbonding period is over
+    pub unbonding: Uint128,
+}
 
 #[cw_serde]
-pub enum LockType {
-    Long {},
-    Short {}
+pub struct PositionResponse {
+    pub id: u64,
+    /// derivative tokens backed by this position
+    pub amount: Uint128,
+    pub tier: String,
+    pub start: Timestamp,
+    pub release: Expiration,
+    /// true once the release expiration has passed
+    pub matured: bool,
+    /// weighted shares above weight one
+    pub boost: Uint128,
+    /// boost rewards paid out when the position is unlocked
+    pub pending_rewards: Uint128,
 }
 
+#[cw_serde]
+pub struct PositionsResponse {
+    pub positions: Vec<PositionResponse>,
+}
+
+#[cw_serde]
+pub struct Approval {
+    pub spender: String,
+    pub expires: Expiration,
+}
+
+#[cw_serde]
+pub struct OwnerOfResponse {
+    pub owner: String,
+    pub approvals: Vec<Approval>,
+}
+
+#[cw_serde]
+pub struct NumTokensResponse {
+    pub count: u64,
+}
+
+#[cw_serde]
+pub struct NftContractInfoResponse {
+    pub name: String,
+    pub symbol: String,
+}
+
+/// PositionReceipt is the cw721 extension describing the locked position
+#[cw_serde]
+pub struct PositionReceipt {
+    /// derivative tokens backed by this position
+    pub amount: Uint128,
+    pub tier: String,
+    pub start: Timestamp,
+    pub release: Expiration,
+}
+
+#[cw_serde]
+pub struct NftInfoResponse {
+    pub token_uri: Option<String>,
+    pub extension: PositionReceipt,
+}
+
+#[cw_serde]
+pub struct AllNftInfoResponse {
+    pub access: OwnerOfResponse,
+    pub info: NftInfoResponse,
+}
+
+#[cw_serde]
+pub struct TokensResponse {
+    pub tokens: Vec<String>,
+}
+
+/// Cw721ReceiveMsg should be de/serialized under `ReceiveNft()` variant in a ExecuteMsg
+#[cw_serde]
+pub struct Cw721ReceiveMsg {
+    pub sender: String,
+    pub token_id: String,
+    pub msg: Binary,
+}
+
+impl Cw721ReceiveMsg {
+    /// creates a cosmos_msg sending this struct to the named contract
+    pub fn into_cosmos_msg<T: Into<String>>(self, contract_addr: T) -> StdResult<CosmosMsg> {
+        let msg = to_binary(&Cw721ReceiverExecuteMsg::ReceiveNft(self))?;
+        Ok(WasmMsg::Execute {
+            contract_addr: contract_addr.into(),
+            msg,
+            funds: vec![],
+        }
+        .into())
+    }
+}
+
+#[cw_serde]
+enum Cw721ReceiverExecuteMsg {
+    ReceiveNft(Cw721ReceiveMsg),
+}
+
+/// UnlockStrategy picks the positions an unlock consumes first
+#[cw_serde]
+pub enum UnlockStrategy {
+    /// matured positions, then tokens without a position, then the positions maturing soonest
+    MaturedFirst {},
+    /// tokens without a position, then the oldest positions
+    Fifo {},
+    /// newest positions first, tokens without a position last
+    Lifo {},
+}
+
+impl Default for UnlockStrategy {
+    fn default() -> Self {
+        UnlockStrategy::Fifo {}
+    }
+}
+
+/// StakingMode is the kind of token locked in the contract
+#[cw_serde]
+pub enum StakingMode {
+    /// the cw20 at staking_token_address, sent with the Lock hook
+    Cw20 {},
+    /// stake_denom coins, sent with ExecuteMsg::Lock
+    Native {},
+}
+
+impl Default for StakingMode {
+    fn default() -> Self {
+        StakingMode::Cw20 {}
+    }
+}
+
+/// SweepToken is a token held by the contract that the owner can sweep
+#[cw_serde]
+pub enum SweepToken {
+    Cw20 { address: String },
+    Native { denom: String },
+}
+
+/// SurplusPolicy decides what happens to staking tokens the contract holds
+/// beyond what it tracks, e.g. after a plain cw20 transfer to it
+#[cw_serde]
+pub enum SurplusPolicy {
+    /// add the surplus to the pool, raising the value of every derivative token
+    Donate {},
+    /// leave the surplus untracked, for the owner to sweep
+    Sweep {},
+}
+
+impl Default for SurplusPolicy {
+    fn default() -> Self {
+        SurplusPolicy::Sweep {}
+    }
+}
+
+impl fmt::Display for SurplusPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SurplusPolicy::Donate {} => write!(f, "donate"),
+            SurplusPolicy::Sweep {} => write!(f, "sweep"),
+        }
+    }
+}
 
 #[cw_serde]
 pub enum Cw20HookMsg {
-    Lock {
-        lock_type: LockType
-    },
+    Lock { tier: String },
+    /// DistributeRewards adds the sent staking tokens to the pool without minting,
+    /// raising the nominal value of every derivative token
+    DistributeRewards {},
+    /// FundRewards streams the sent staking tokens into the pool linearly between start and end.
+    /// Only the owner can fund, and tokens left from an earlier schedule are added to the new one
+    FundRewards { start: Timestamp, end: Timestamp },
+    /// AddRewards shares the sent reward tokens among the current derivative token holders,
+    /// who collect them with ClaimRewards
+    AddRewards {},
 }
 
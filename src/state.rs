@@ -1,42 +1,57 @@
 use cosmwasm_schema::cw_serde;
-use cosmwasm_std::{Addr, CanonicalAddr, Decimal, Uint128};
+use cosmwasm_std::{Addr, BlockInfo, CanonicalAddr, Decimal, Timestamp, Uint128};
 use cw_controllers::Claims;
-use cw_storage_plus::Item;
-use cw_utils::Duration;
+use cw_storage_plus::{Index, IndexList, IndexedMap, Item, Map, MultiIndex};
+use cw_utils::{Duration, Expiration};
+
+use crate::msg::{StakingMode, SurplusPolicy};
 
 pub const CLAIMS: Claims = Claims::new("claims");
 
+/// LockTier is one of the lock options the owner offers
 #[cw_serde]
-pub struct LockPrd {
-    pub long: Duration,
-    pub short: Duration,
+pub struct LockTier {
+    /// after this period, positions of the tier are matured
+    pub period: Duration,
+    /// This is how much the owner takes as a cut when someone locks
+    pub tax: Decimal,
+    /// This is how much the staker pays for unlocking before the period
+    pub penalty: Decimal,
+    /// reward multiplier of the tier, one means no boost
+    pub weight: Decimal,
 }
 
+/// Delegation stakes the locked native tokens with validators
 #[cw_serde]
-pub struct LockTax {
-    pub long: Decimal,
-    pub short: Decimal,
+pub struct Delegation {
+    pub validators: Vec<String>,
+    /// how long the chain holds undelegated tokens, claims are released after it
+    pub unbonding_period: Duration,
 }
 
-
 #[cw_serde]
 pub struct StakingInfo {
     /// Owner created the contract and takes a cut
     pub owner: Addr,
     /// staking token denom
     pub stake_denom: String,
-    /// after this perio, you can get back your qtum token, 
-    /// 
     pub staking_token_address: CanonicalAddr,
-    pub period : LockPrd,
-    /// This is how much the owner takes as a cut when someone unstake
-    pub tax: LockTax,
-    /// This is how much the staker pay for unstake the qtum before period
-    pub penalty: Decimal
+    /// cw20 paid out by ClaimRewards, it may differ from the staking token
+    #[serde(default)]
+    pub reward_token_address: Option<CanonicalAddr>,
+    #[serde(default)]
+    pub surplus_policy: SurplusPolicy,
+    #[serde(default)]
+    pub staking_mode: StakingMode,
+    /// set when the locked tokens are delegated, native mode only
+    #[serde(default)]
+    pub delegation: Option<Delegation>,
+    /// derivative tokens and receipts of unmatured positions can't be transferred,
+    /// otherwise the positions move along with the tokens
+    #[serde(default)]
+    pub lock_transfers: bool,
 }
 
-
-
 /// Supply is dynamic and tracks the current supply of staked and ERC20 tokens.
 #[cw_serde]
 #[derive(Default)]
@@ -45,11 +60,191 @@ pub struct Supply {
     pub issued: Uint128,
     /// bonded is how many qtum tokens locked on this contract
     pub locked: Uint128,
-    /// fees is how many qtum tokens collected tax and penalty
+    /// claims is how many qtum tokens are unlocked and waiting to be claimed
+    pub claims: Uint128,
+    /// fees is how many qtum tokens collected tax and penalty, and not yet withdrawn by the owner
     pub fees: Uint128,
 }
 
+/// RewardSchedule streams `total` staking tokens into `Supply.locked` linearly between start and end
+#[cw_serde]
+pub struct RewardSchedule {
+    pub start: Timestamp,
+    pub end: Timestamp,
+    /// how many tokens the schedule releases over the whole window
+    pub total: Uint128,
+    /// how many tokens have been added to the pool so far
+    pub distributed: Uint128,
+}
+
+impl RewardSchedule {
+    /// emitted is how many tokens the schedule has released by `time`
+    pub fn emitted(&self, time: Timestamp) -> Uint128 {
+        if time <= self.start {
+            Uint128::zero()
+        } else if time >= self.end {
+            self.total
+        } else {
+            self.total.multiply_ratio(
+                time.seconds() - self.start.seconds(),
+                self.end.seconds() - self.start.seconds(),
+            )
+        }
+    }
+
+    /// rate is how many tokens are released per second at `time`
+    pub fn rate(&self, time: Timestamp) -> Decimal {
+        if time < self.start || time >= self.end {
+            Decimal::zero()
+        } else {
+            Decimal::from_ratio(self.total, self.end.seconds() - self.start.seconds())
+        }
+    }
+
+    /// undistributed is how many tokens the contract holds for the schedule
+    pub fn undistributed(&self) -> Uint128 {
+        self.total - self.distributed
+    }
+}
+
+/// Boost tracks the part of the streamed rewards that goes to weights above one.
+/// Every share earns the base rate through `Supply.locked`, and positions with a higher
+/// weight earn the rest from `reward_per_weight` on their extra weighted shares.
+#[cw_serde]
+#[derive(Default)]
+pub struct Boost {
+    /// sum of the boost of all positions
+    pub weight: Uint128,
+    /// boost rewards earned so far by one extra weighted share
+    pub reward_per_weight: Decimal,
+    /// boost rewards accrued and not paid out yet
+    pub pending: Uint128,
+}
+
+/// RewardPool is the accumulator for rewards claimed with ClaimRewards
+#[cw_serde]
+#[derive(Default)]
+pub struct RewardPool {
+    /// reward tokens earned so far by one derivative token
+    pub reward_per_share: Decimal,
+    /// reward tokens added and not claimed yet
+    pub pending: Uint128,
+}
+
+/// UserRewards is the accumulator state of one derivative token holder
+#[cw_serde]
+#[derive(Default)]
+pub struct UserRewards {
+    /// balance * reward_per_share at the last balance change, already accounted for
+    pub reward_debt: Uint128,
+    /// rewards earned and not claimed yet
+    pub pending: Uint128,
+}
+
+/// Fees is how much of each fee kind has been collected over the contract lifetime
+#[cw_serde]
+#[derive(Default)]
+pub struct Fees {
+    /// entry tax taken on lock
+    pub tax: Uint128,
+    /// early-exit penalty taken on unlock
+    pub penalty: Uint128,
+}
+
+/// PendingOwner is an ownership transfer proposed by the owner, waiting to be accepted
+#[cw_serde]
+pub struct PendingOwner {
+    /// the proposed new owner
+    pub owner: Addr,
+    /// the proposal can no longer be accepted after this
+    pub expiry: Option<Expiration>,
+}
 
 pub const STAKING_INFO: Item<StakingInfo> = Item::new("staking_info");
 
+/// TIERS are the lock options new positions can pick, keyed by tier id
+pub const TIERS: Map<&str, LockTier> = Map::new("tiers");
+
 pub const TOTAL_SUPPLY: Item<Supply> = Item::new("total_supply");
+
+pub const FEES: Item<Fees> = Item::new("fees");
+
+pub const REWARD_SCHEDULE: Item<RewardSchedule> = Item::new("reward_schedule");
+
+pub const BOOST: Item<Boost> = Item::new("boost");
+
+pub const REWARD_POOL: Item<RewardPool> = Item::new("reward_pool");
+
+pub const USER_REWARDS: Map<&Addr, UserRewards> = Map::new("user_rewards");
+
+pub const PENDING_OWNER: Item<PendingOwner> = Item::new("pending_owner");
+
+/// LockPosition is a single lock made by a user, kept until it is fully unlocked.
+#[cw_serde]
+pub struct LockPosition {
+    /// owner of the locked position
+    pub owner: Addr,
+    /// how many derivative tokens this position still backs
+    pub amount: Uint128,
+    /// id of the tier the position was opened or last extended with
+    pub tier: String,
+    /// lock period of the tier when the position was opened or last extended
+    pub period: Duration,
+    /// tax paid for the tier when the position was opened or last extended
+    pub tax: Decimal,
+    /// weight of the tier when the position was opened or last extended
+    pub weight: Decimal,
+    /// block time the position was opened
+    pub start: Timestamp,
+    /// after this expiration the position is matured
+    pub release: Expiration,
+    /// penalty for unlocking before release, fixed when the position was opened
+    pub penalty: Decimal,
+    /// weighted shares above weight one, these earn the boost rewards
+    pub boost: Uint128,
+    /// Boost.reward_per_weight when the position last collected its boost rewards
+    pub reward_index: Decimal,
+    /// boost rewards earned before reward_index and not paid out yet
+    #[serde(default)]
+    pub boost_rewards: Uint128,
+    /// spenders allowed to transfer the position receipt, cleared when it moves
+    #[serde(default)]
+    pub approvals: Vec<PositionApproval>,
+}
+
+#[cw_serde]
+pub struct PositionApproval {
+    pub spender: Addr,
+    pub expires: Expiration,
+}
+
+impl PositionApproval {
+    pub fn is_expired(&self, block: &BlockInfo) -> bool {
+        self.expires.is_expired(block)
+    }
+}
+
+/// OPERATORS are allowed to move every position receipt of an owner, keyed by (owner, operator)
+pub const OPERATORS: Map<(&Addr, &Addr), Expiration> = Map::new("operators");
+
+pub struct PositionIndexes<'a> {
+    pub owner: MultiIndex<'a, Addr, LockPosition, u64>,
+}
+
+impl<'a> IndexList<LockPosition> for PositionIndexes<'a> {
+    fn get_indexes(&'_ self) -> Box<dyn Iterator<Item = &'_ dyn Index<LockPosition>> + '_> {
+        let v: Vec<&dyn Index<LockPosition>> = vec![&self.owner];
+        Box::new(v.into_iter())
+    }
+}
+
+/// positions are keyed by a global id and indexed by owner
+pub fn positions<'a>() -> IndexedMap<'a, u64, LockPosition, PositionIndexes<'a>> {
+    let indexes = PositionIndexes {
+        owner: MultiIndex::new(|_pk, p| p.owner.clone(), "positions", "positions__owner"),
+    };
+    IndexedMap::new("positions", indexes)
+}
+
+/// last position id handed out
+pub const POSITION_COUNT: Item<u64> = Item::new("position_count");